@@ -0,0 +1,145 @@
+use crate::{
+    database::format::ResultTable,
+    requests::{self, Endpoint},
+};
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+const CACHE_CAPACITY: usize = 256;
+const CACHE_TTL: Duration = Duration::from_secs(300);
+// Parameters that only change how a result is rendered, so responses differing only by them share an entry
+const PRESENTATION_PARAMETERS: [&str; 1] = ["format"];
+// Endpoints that must run every time they are requested, on top of requests::WRITE_URIS; run-query is included as
+// the query it runs may write, and fantasy-points as its ruleset file can change without the database changing
+const UNCACHED_URIS: [&str; 2] = ["run-query", "fantasy-points"];
+
+// Shared by every connection thread; must be invalidated whenever the database's contents change
+pub static QUERY_CACHE: LazyLock<Mutex<QueryCache>> = LazyLock::new(|| Mutex::new(QueryCache::new()));
+
+struct CacheEntry {
+    table: ResultTable,
+    inserted: Instant,
+    last_used: u64,
+}
+
+pub struct QueryCache {
+    entries: HashMap<String, CacheEntry>,
+    // Incremented on every access (for LRU ordering) and on every invalidation (for generation checks)
+    clock: u64,
+    generation: u64,
+    hits: u64,
+    misses: u64,
+    invalidations: u64,
+}
+impl QueryCache {
+    fn new() -> Self {
+        QueryCache {
+            entries: HashMap::new(),
+            clock: 0,
+            generation: 0,
+            hits: 0,
+            misses: 0,
+            invalidations: 0,
+        }
+    }
+
+    pub fn key(endpoint: &Endpoint) -> Option<String> {
+        // Normalized endpoint: uri plus its query parameters sorted by name; None if it must not be cached
+        // The request is validated first, so that e.g. an omitted parameter and its default share an entry; a request
+        // failing validation is not cached, as it only yields an error
+        let endpoint = &endpoint.validated().ok()?;
+        let uri = endpoint.uri.as_str();
+        if requests::WRITE_URIS.contains(&uri) || UNCACHED_URIS.contains(&uri) {
+            return None;
+        }
+        let mut parameters: Vec<String> = endpoint
+            .query_pv_map
+            .iter()
+            .filter(|(p, _)| !PRESENTATION_PARAMETERS.contains(&p.as_str()))
+            // Encoded as in a URI, so that values containing separators cannot collide with other parameters or lists
+            .map(|(p, vals)| {
+                let vals: Vec<String> = vals.iter().map(|v| requests::percent_encode(v)).collect();
+                format!("{}={}", requests::percent_encode(p), vals.join(","))
+            })
+            .collect();
+        parameters.sort();
+        Some(format!("{}?{}", endpoint.uri, parameters.join("&")))
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<ResultTable> {
+        self.clock += 1;
+        let table = match self.entries.get_mut(key) {
+            Some(entry) if entry.inserted.elapsed() < CACHE_TTL => {
+                entry.last_used = self.clock;
+                Some(entry.table.clone())
+            }
+            Some(_) => {
+                self.entries.remove(key);
+                None
+            }
+            None => None,
+        };
+        match table {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+        table
+    }
+
+    pub fn insert(&mut self, key: String, table: ResultTable, generation: u64) {
+        // Results computed before the latest invalidation may be stale, so they are dropped
+        if generation != self.generation {
+            return;
+        }
+        if self.entries.len() >= CACHE_CAPACITY && !self.entries.contains_key(&key) {
+            if let Some(lru) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            {
+                self.entries.remove(&lru);
+            }
+        }
+        self.clock += 1;
+        self.entries.insert(
+            key,
+            CacheEntry {
+                table,
+                inserted: Instant::now(),
+                last_used: self.clock,
+            },
+        );
+    }
+
+    pub fn invalidate(&mut self) {
+        self.entries.clear();
+        self.generation += 1;
+        self.invalidations += 1;
+    }
+
+    pub fn stats(&self) -> String {
+        let lookups = self.hits + self.misses;
+        format!(
+            "Cache: {} hit(s), {} miss(es) ({:.1}% hit rate), {} invalidation(s), {}/{} entries, {}s TTL",
+            self.hits,
+            self.misses,
+            if lookups == 0 {
+                0.0
+            } else {
+                self.hits as f64 * 100.0 / lookups as f64
+            },
+            self.invalidations,
+            self.entries.len(),
+            CACHE_CAPACITY,
+            CACHE_TTL.as_secs()
+        )
+    }
+}
@@ -1,25 +1,50 @@
 use crate::{
     common::{self, println_then_show_input_indicator, InputAction},
-    requests,
+    database::{
+        self,
+        format::{pretty_json, OutputFormat, TEXT_SEPARATOR},
+    },
+    http,
+    requests::{self, Endpoint, Parameter, ParameterType},
+    version::{self, VersionStatus},
 };
 use queue::Queue;
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::{collections::HashMap, io::ErrorKind};
-use std::{
-    io::BufRead,
-    sync::atomic::{AtomicBool, Ordering},
-};
-use std::{io::BufReader, net::TcpStream};
 use std::{
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 const SERVER_ADDR: &str = "127.0.0.1:7878";
 const CONNECT_INIT_ERROR_TIMEOUT_MS: u64 = 1000;
 const CONNECT_MAX_ERROR_TIMEOUT_MS: u128 = 5000;
 const CONNECT_MAX_TRIES: u8 = 10;
+// Width, in characters, of the longest bar of a distribution histogram
+const HISTOGRAM_BAR_WIDTH: usize = 40;
+// How long to wait for each of the server's answers to the handshake and endpoint list after connecting
+const CONNECT_RESPONSE_TIMEOUT_MS: u64 = 5000;
+// Enum parameters with at most this many allowed values list them in the help text; others show their type
+const HELP_MAX_LISTED_VALUES: usize = 6;
+
+// A request sent to the server whose response has not arrived yet, keyed by its request id
+struct PendingRequest {
+    command: String,
+    sent: Instant,
+}
+
+// Requests awaiting a response; shared by the input loop, which sends them, and the stream handler, which receives
+type PendingRequests = Arc<Mutex<HashMap<u64, PendingRequest>>>;
+
+// A response body, and the label it is printed under: the command that caused it and the round trip time
+#[derive(Clone)]
+struct LabelledResponse {
+    label: String,
+    body: String,
+}
 
 const _HELP_MSG: &str = "
  ----------------------------------------------------------------------
@@ -30,64 +55,222 @@ const _HELP_MSG: &str = "
     e.g.:
         $ get-all-players name=\"Smith\"
         $ get-player player_id=12345 statistics=goals,assists,shots,saves
+        $ get-player player_id=12345 statistics=all
+        $ best-xi formation=4-3-3 club=Arsenal score=goals,assists:0.5
+        $ fantasy-points ruleset=default position=Defender limit=10
+        $ validate rule=shots-on-target-within-shots
+        $ get-all-players position=CB
+        $ set-sub-positions player_id=12345 sub_positions=CB,FB
+        $ aggregate group_by=club stats=goals,assists fn=median
+        $ distribution statistic=goals position=Forward bins=8
+        $ correlations statistics=goals,shots,assists,passes method=spearman
+        $ correlations statistics=all view=pairs top=5
+        $ create-watchlist user=alex list=strikers
+        $ add-to-watchlist user=alex list=strikers player_id=12345,12346
+        $ set-note user=alex player_id=12345 note=\"Strong in the air\" tags=aerial,u23
+        $ get-watchlist user=alex list=strikers statistics=goals,assists
+        $ save-query name=young-strikers query=\"get-player position={position} statistics=goals,assists\"
+        $ run-query young-strikers position=Forward
+    Any request also accepts format=text|csv|json|ndjson|markdown|html, e.g.:
+        $ get-all-players name=\"Smith\" format=csv
+        $ get-player player_id=12345 format=json
+    and explain=true, which returns the query plan of the request's queries instead of their results
+    (not accepted by requests that write, e.g. set-note, as they would still write)
+    Commas separate the values of a parameter, except within double quotes or escaped as \\, e.g.:
+        $ create-watchlist user=alex list=\"strikers, u23\"
+        $ create-watchlist user=alex list=strikers\\,u23
+    End a line with ? to list the endpoints, parameters or values that can complete it, e.g.:
+        $ get-?
+        $ aggregate ?
+        $ aggregate fn=?
 ";
 
-fn print_help() {
+fn type_label(kind: &ParameterType) -> String {
+    let values = kind.allowed_values();
+    match kind {
+        ParameterType::Enum(_) if values.len() <= HELP_MAX_LISTED_VALUES => format!("<{}>", values.join("|")),
+        kind => format!("<{}>", kind.name()),
+    }
+}
+
+fn usage(parameter: &Parameter) -> String {
+    // e.g. "stats=<statistic>,..."
+    let list = if parameter.multi { ",..." } else { "" };
+    format!("{}={}{}", parameter.name, type_label(&parameter.kind), list)
+}
+
+fn synopsis(endpoint: &Endpoint) -> String {
+    // e.g. "aggregate group_by=<club|position|nationality|age_bucket> stats=<statistic>,... [fn=<sum|avg|...>]"
+    let mut synopsis = endpoint.uri.clone();
+    for p in &endpoint.parameters {
+        let usage = usage(p);
+        synopsis.push_str(&match p.required {
+            true => format!(" {}", usage),
+            false => format!(" [{}]", usage),
+        });
+    }
+    synopsis
+}
+
+fn print_help(endpoints: &[Endpoint]) {
+    // The endpoint list comes from the server, so it covers endpoints added since the client was built
     println!("{_HELP_MSG}");
+    println!("[ENDPOINTS]");
+    for endpoint in endpoints {
+        println!("    {}\n        {}", synopsis(endpoint), endpoint.description);
+    }
+    println!();
 }
 
-fn parse_input<'a>(buf: &'a str, shutdown_trigger: &'a Arc<AtomicBool>) -> Result<String, &'a str> {
-    let mut argsplit: Vec<String> = buf.split(' ').map(|e| e.to_owned()).collect();
-    // Merge double-quote strings into single args
-    let double_quote_args: Vec<(usize, String)> = argsplit
-        .iter()
-        .cloned()
-        .enumerate()
-        .filter(|(_, a)| a.contains('\"') && !a.ends_with('\"'))
-        .collect();
-    double_quote_args.iter().for_each(|(i, _)| {
-        let idx = *i;
-        let mut join_vec = vec![];
-        loop {
-            let val = argsplit.remove(idx);
-            join_vec.push(val.clone());
-            if val.ends_with('\"') {
-                break;
+fn complete(partial: &str, endpoints: &[Endpoint]) -> Vec<String> {
+    // Candidates for the last word of partial (a command line without its trailing '?'): an endpoint, a parameter
+    // of the line's endpoint, or a value of a parameter
+    let args: Vec<&str> = partial.split(' ').collect();
+    if args.len() == 1 {
+        return endpoints
+            .iter()
+            .filter(|e| e.uri.starts_with(args[0]))
+            .map(|e| format!("{} - {}", e.uri, e.description))
+            .collect();
+    }
+    let Some(endpoint) = endpoints.iter().find(|e| e.uri == args[0]) else {
+        return vec![];
+    };
+    let last = args.last().unwrap();
+    match last.split_once('=') {
+        Some((name, values)) => {
+            let Some(parameter) = endpoint.parameters.iter().find(|p| p.name == name) else {
+                return vec![];
+            };
+            // Only the value being typed is completed, i.e. the one after the last comma
+            let value = values.rsplit(requests::LIST_SEPARATOR).next().unwrap_or("");
+            let allowed: Vec<String> = parameter
+                .kind
+                .allowed_values()
+                .iter()
+                .filter(|v| v.starts_with(value))
+                .cloned()
+                .collect();
+            match parameter.kind.allowed_values().is_empty() {
+                true => vec![format!("{}: {}", name, parameter.kind.describe())],
+                false => allowed,
             }
         }
-        argsplit.insert(idx, join_vec.join(" "));
-    });
+        None => endpoint
+            .parameters
+            .iter()
+            .filter(|p| p.name.starts_with(last))
+            .map(|p| {
+                format!(
+                    "{} - {} ({}{})",
+                    usage(p),
+                    p.description,
+                    if p.required { "required" } else { "optional" },
+                    p.default.as_ref().map_or(String::new(), |d| format!(", default {}", d))
+                )
+            })
+            .collect(),
+    }
+}
+
+fn parse_input(buf: &str, shutdown_trigger: &Arc<AtomicBool>, endpoints: &[Endpoint]) -> Result<Endpoint, String> {
+    // Quoted values are kept as single args; saved queries are split the same way on the server
+    let mut argsplit: Vec<String> = requests::split_args(buf);
+    if argsplit.is_empty() {
+        return Err("No such Endpoint exists".to_owned());
+    }
 
     // Check for CLI input actions
     if let Some(action) = common::parse_input_action(&argsplit) {
         match action {
             InputAction::Quit => {
                 shutdown_trigger.store(true, Ordering::Relaxed);
-                return Err("Shutdown requested");
+                return Err("Shutdown requested".to_owned());
             }
-            InputAction::Help => print_help(),
-            InputAction::ListConnections => (),
+            InputAction::Help => print_help(endpoints),
+            InputAction::ListConnections
+            | InputAction::Validate
+            | InputAction::Backup
+            | InputAction::Vacuum
+            | InputAction::Analyze
+            | InputAction::IntegrityCheck
+            | InputAction::CacheStats => (),
         }
     }
     // Parse and verify endpoint
-    let mut endpoint = if let Some(e) = requests::clone_authoritative_endpoint_by_uri(argsplit.remove(0).as_str()) {
-        e
-    } else {
-        return Err("No such Endpoint exists");
+    let authority = match endpoints.iter().find(|e| e.uri == argsplit[0]) {
+        Some(e) => e,
+        None => return Err("No such Endpoint exists".to_owned()),
     };
+    argsplit.remove(0);
+    let mut endpoint = authority.clone();
     let mut query_pv_map: HashMap<String, Vec<String>> = HashMap::new();
     // Parse and verify query parameters and associated values
-    while !argsplit.is_empty() {
-        let mut query_kv_split: Vec<String> = argsplit.pop().unwrap().split('=').map(|e| e.to_owned()).collect();
-        if query_kv_split.len() != 2 {
-            return Err("Malformed input (couldn't parse query parameter-value pair)");
-        }
-        let vals: Vec<String> = query_kv_split.pop().unwrap().split(',').map(|e| e.to_owned()).collect();
-        let param = query_kv_split.pop().unwrap();
-        query_pv_map.insert(param, vals);
+    while let Some(arg) = argsplit.pop() {
+        // Values may themselves contain '=' (e.g. the query of save-query), so only the first one separates
+        let (param, vals) = match arg.split_once('=') {
+            Some((param, vals)) => (param.to_owned(), vals),
+            // A bare argument is taken as the endpoint's positional parameter, e.g. "run-query young-strikers"
+            None => match requests::POSITIONAL_PARAMETERS
+                .iter()
+                .find(|(uri, _)| *uri == endpoint.uri)
+            {
+                Some((_, param)) => (param.to_string(), arg.as_str()),
+                None => return Err("Malformed input (couldn't parse query parameter-value pair)".to_owned()),
+            },
+        };
+        // A quoted value is taken whole; otherwise commas separate values, and \, is a literal comma
+        query_pv_map.insert(param, requests::parse_values(vals));
     }
     endpoint.query_pv_map = query_pv_map;
-    Ok(endpoint.get_request_string())
+    // Checked against the same schema as the server checks it, so mistakes are reported without a round trip
+    endpoint.validate(authority, false).map_err(|e| e.to_string())?;
+    Ok(endpoint)
+}
+
+fn response_label(head: &[String], pending: &PendingRequests) -> String {
+    // e.g. "#3 get-player player_id=9 (12 ms)"; responses without a known request id are labelled as such
+    let request_id = http::header(head, http::REQUEST_ID_HEADER).and_then(|id| id.parse::<u64>().ok());
+    let request = request_id.and_then(|id| pending.lock().unwrap().remove(&id));
+    match (request_id, request) {
+        (Some(id), Some(request)) => format!(
+            "#{} {} ({} ms)",
+            id,
+            request.command,
+            request.sent.elapsed().as_millis()
+        ),
+        _ => "(unknown request)".to_owned(),
+    }
+}
+
+fn render_histogram(response: &str) -> Option<String> {
+    // Draws the bins of a text-formatted distribution response as an ASCII bar chart; None for any other response
+    let mut lines = response.lines();
+    if !lines.next()?.split(TEXT_SEPARATOR).eq(database::DISTRIBUTION_COLUMNS) {
+        return None;
+    }
+    let bins: Vec<(String, usize)> = lines
+        .filter_map(|line| {
+            let cells: Vec<&str> = line.split(TEXT_SEPARATOR).collect();
+            if cells.len() != 4 || cells[0] != database::DISTRIBUTION_BIN_MEASURE {
+                return None;
+            }
+            let (lower, upper) = (cells[1].parse::<f64>().ok()?, cells[2].parse::<f64>().ok()?);
+            Some((format!("[{:.2}, {:.2}]", lower, upper), cells[3].parse().ok()?))
+        })
+        .collect();
+    let max_count = bins.iter().map(|(_, count)| *count).max()?.max(1);
+    let label_width = bins.iter().map(|(label, _)| label.len()).max()?;
+    Some(
+        bins.iter()
+            .map(|(label, count)| {
+                // Rounded up, so that non-empty bins always show at least one character
+                let bar = "#".repeat((count * HISTOGRAM_BAR_WIDTH).div_ceil(max_count));
+                format!("{:>width$} | {} {}", label, bar, count, width = label_width)
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+    )
 }
 
 fn try_connect() -> TcpStream {
@@ -126,6 +309,56 @@ fn try_connect() -> TcpStream {
     sock
 }
 
+fn round_trip(mut stream: &TcpStream, request: &Endpoint) -> Option<(Vec<String>, String)> {
+    // Sends request and waits for its response, before the stream handler takes the stream over
+    stream
+        .write_all(request.get_request_string(SERVER_ADDR).as_bytes())
+        .ok()?;
+    stream
+        .set_read_timeout(Some(Duration::from_millis(CONNECT_RESPONSE_TIMEOUT_MS)))
+        .ok()?;
+    let mut receive_buf: Vec<u8> = vec![];
+    let mut read_buf = [0u8; 4096];
+    let response = loop {
+        if let Some(response) = http::take_response(&mut receive_buf) {
+            break response;
+        }
+        match stream.read(&mut read_buf) {
+            Ok(0) | Err(_) => return None,
+            Ok(len) => receive_buf.extend_from_slice(&read_buf[..len]),
+        }
+    };
+    stream.set_read_timeout(None).ok()?;
+    match response.0.first()?.split(' ').nth(1) {
+        Some("200") => Some(response),
+        _ => None,
+    }
+}
+
+fn handshake(stream: &TcpStream) -> Option<String> {
+    // Offers every API version this build speaks, newest first, and returns the one the server picked; None if it
+    // does not take part in the handshake
+    let mut versions: Vec<String> = version::version_names().into_iter().map(String::from).collect();
+    versions.reverse();
+    let mut request = Endpoint::new(
+        version::HANDSHAKE_URI,
+        HashMap::from([("versions".to_owned(), versions)]),
+    );
+    request.api_version = version::UNVERSIONED.to_owned();
+    let (head, _) = round_trip(stream, &request)?;
+    let agreed = http::header(&head, "api-version")?;
+    version::status(agreed)?;
+    Some(agreed.to_owned())
+}
+
+fn fetch_endpoints(stream: &TcpStream, api_version: &str) -> Option<Vec<Endpoint>> {
+    // Asks the server for its endpoints and their parameters; None if it does not answer with a list of them
+    let mut request = Endpoint::new("endpoints", HashMap::new());
+    request.api_version = api_version.to_owned();
+    let (_, body) = round_trip(stream, &request)?;
+    requests::endpoints_from_text(&body)
+}
+
 fn _assertion_checks() {
     assert!((CONNECT_INIT_ERROR_TIMEOUT_MS as u128) < CONNECT_MAX_ERROR_TIMEOUT_MS);
 }
@@ -133,12 +366,14 @@ fn _assertion_checks() {
 fn handle_stream(
     stream: TcpStream,
     send_queue: Arc<Mutex<Queue<String>>>,
-    receive_queue: Arc<Mutex<Queue<String>>>,
+    receive_queue: Arc<Mutex<Queue<LabelledResponse>>>,
+    pending: PendingRequests,
     shutdown_trigger: Arc<AtomicBool>,
 ) {
     stream.set_nonblocking(true).expect("Failed to set stream nonblocking");
+    // Bytes received but not yet parsed into a complete response
     let mut receive_buf: Vec<u8> = vec![];
-    let mut buf_reader = BufReader::new(&stream);
+    let mut read_buf = [0u8; 4096];
     loop {
         if shutdown_trigger.load(Ordering::Relaxed) {
             break;
@@ -158,18 +393,27 @@ fn handle_stream(
         // Allow other parties to modify send_queue
         // thread::sleep(Duration::from_millis(50));
         {
-            receive_buf.clear();
             let mut receive_q_locked = receive_queue.lock().unwrap();
-            match buf_reader.read_until(requests::REQUEST_TERMINATOR, &mut receive_buf) {
+            match (&stream).read(&mut read_buf) {
                 Ok(len) => {
                     if len == 0 {
                         println!("Detected stream dropped!");
                         shutdown_trigger.store(true, Ordering::Relaxed);
                         return;
                     }
-                    receive_q_locked
-                        .queue(String::from_utf8(receive_buf.clone()).unwrap())
-                        .unwrap();
+                    receive_buf.extend_from_slice(&read_buf[..len]);
+                    // Responses are framed by Content-Length, so a read may hold part of one or several of them
+                    while let Some((head, body)) = http::take_response(&mut receive_buf) {
+                        // JSON comes compact off the wire, so is re-indented for display
+                        let is_json = http::header(&head, "content-type")
+                            .is_some_and(|t| t.starts_with(OutputFormat::Json.media_type()));
+                        receive_q_locked
+                            .queue(LabelledResponse {
+                                label: response_label(&head, &pending),
+                                body: if is_json { pretty_json(&body) } else { body },
+                            })
+                            .unwrap();
+                    }
                 }
                 Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
                     if shutdown_trigger.load(Ordering::Relaxed) {
@@ -192,8 +436,10 @@ pub fn run() {
     );
     let send_queue: Arc<Mutex<Queue<String>>> = Arc::new(Mutex::new(Queue::new()));
     let send_queue_stream_handler: Arc<Mutex<Queue<String>>> = send_queue.clone();
-    let receive_queue: Arc<Mutex<Queue<String>>> = Arc::new(Mutex::new(Queue::new()));
-    let receive_queue_stream_handler: Arc<Mutex<Queue<String>>> = receive_queue.clone();
+    let receive_queue: Arc<Mutex<Queue<LabelledResponse>>> = Arc::new(Mutex::new(Queue::new()));
+    let receive_queue_stream_handler: Arc<Mutex<Queue<LabelledResponse>>> = receive_queue.clone();
+    let pending: PendingRequests = Arc::new(Mutex::new(HashMap::new()));
+    let pending_stream_handler: PendingRequests = pending.clone();
 
     // Setup Ctrl-C Handler
     ctrlc::set_handler(move || {
@@ -208,9 +454,13 @@ pub fn run() {
     let receive_thread_handle = thread::spawn(move || loop {
         if let Ok(mut receive_q_locked) = receive_queue.lock() {
             while !receive_q_locked.is_empty() {
+                let response = receive_q_locked.dequeue().unwrap();
+                let histogram = render_histogram(&response.body)
+                    .map(|h| format!("\n{}", h))
+                    .unwrap_or_default();
                 println_then_show_input_indicator(format!(
-                    "[SERVER RESPONSE]\n{}",
-                    receive_q_locked.dequeue().unwrap()
+                    "[SERVER RESPONSE] {}\n{}{}",
+                    response.label, response.body, histogram
                 ));
             }
         }
@@ -219,17 +469,41 @@ pub fn run() {
         }
         thread::sleep(Duration::from_millis(50));
     });
+    let stream = try_connect();
+    let api_version = handshake(&stream).unwrap_or_else(|| {
+        // e.g. a server predating versioning, which only understands unversioned paths
+        println!("[WARNING] Unable to agree on an API version with the server, using unversioned paths");
+        version::UNVERSIONED.to_owned()
+    });
+    if version::status(&api_version) == Some(VersionStatus::Deprecated) {
+        println!(
+            "[WARNING] Using deprecated API version {}: the server speaks no newer one",
+            api_version
+        );
+    }
+    let mut endpoints = fetch_endpoints(&stream, &api_version).unwrap_or_else(|| {
+        // e.g. a server predating the endpoints endpoint; this build's endpoints are the best guess
+        println!("[WARNING] Unable to fetch the server's endpoints, using the client's own");
+        requests::AUTHORITATIVE_ENDPOINTS().to_vec()
+    });
+    // Every request is built from one of these, so is sent as the agreed version
+    for endpoint in &mut endpoints {
+        endpoint.api_version = api_version.clone();
+    }
     // Setup stream handler
     let stream_handler = thread::spawn(|| {
         handle_stream(
-            try_connect(),
+            stream,
             send_queue_stream_handler,
             receive_queue_stream_handler,
+            pending_stream_handler,
             stream_shutdown_trigger,
         )
     });
-    print_help();
+    print_help(&endpoints);
     let mut buf: String = String::new();
+    // Id of the last request sent; each request is sent with its own, which its response is labelled by
+    let mut next_request_id: u64 = 0;
     loop {
         if shutdown_trigger.load(Ordering::SeqCst) {
             break;
@@ -239,16 +513,36 @@ pub fn run() {
         io::stdout().flush().unwrap();
         io::stdin().read_line(&mut buf).unwrap();
         let buf = buf.trim();
+        if let Some(partial) = buf.strip_suffix('?') {
+            let completions = complete(partial, &endpoints);
+            match completions.is_empty() {
+                true => println!("[ERROR] No completions"),
+                false => println!("{}", completions.join("\n")),
+            }
+            continue;
+        }
 
         if let Ok(mut send_q_locked) = send_queue.lock() {
-            let request_string = match parse_input(buf, &shutdown_trigger) {
-                Ok(s) => s,
+            let mut endpoint = match parse_input(buf, &shutdown_trigger, &endpoints) {
+                Ok(e) => e,
                 Err(err) => {
                     println!("[ERROR] {}", err);
                     continue;
                 }
             };
-            send_q_locked.queue(request_string).unwrap();
+            // Registered before it is sent, so the response cannot arrive ahead of it
+            next_request_id += 1;
+            endpoint
+                .headers
+                .insert(http::REQUEST_ID_HEADER.to_owned(), next_request_id.to_string());
+            pending.lock().unwrap().insert(
+                next_request_id,
+                PendingRequest {
+                    command: buf.to_owned(),
+                    sent: Instant::now(),
+                },
+            );
+            send_q_locked.queue(endpoint.get_request_string(SERVER_ADDR)).unwrap();
         }
     }
     println!("Cleaning up stream handler");
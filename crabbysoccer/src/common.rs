@@ -25,13 +25,25 @@ pub enum InputAction {
     Quit,
     Help,
     ListConnections,
+    Validate,
+    Backup,
+    Vacuum,
+    Analyze,
+    IntegrityCheck,
+    CacheStats,
 }
 
-pub const INPUT_ACTION_PARSE_DEFS: [((&str, &str), InputAction); 3] = [
+pub const INPUT_ACTION_PARSE_DEFS: [((&str, &str), InputAction); 9] = [
     // Format: ((INPUT_PATTERN, INPUT_PATTERN_SHORTHAND), InputAction::{})
     (("quit", "q"), InputAction::Quit),
     (("help", "h"), InputAction::Help),
     (("list-connections", "lc"), InputAction::ListConnections),
+    (("validate", "v"), InputAction::Validate),
+    (("backup", "bk"), InputAction::Backup),
+    (("vacuum", "vac"), InputAction::Vacuum),
+    (("analyze", "an"), InputAction::Analyze),
+    (("integrity-check", "ic"), InputAction::IntegrityCheck),
+    (("cache-stats", "cs"), InputAction::CacheStats),
 ];
 
 pub fn parse_input_action<T: ToString>(args: &[T]) -> Option<InputAction> {
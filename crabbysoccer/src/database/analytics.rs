@@ -0,0 +1,414 @@
+use super::{format::ResultTable, DbError, PlayerStatistics, PlayerStore, DB};
+use rusqlite::types::Value;
+use std::collections::BTreeMap;
+
+// Groups a player belongs to; None groups players missing the attribute
+type GroupKeys = fn(&PlayerStatistics) -> Vec<Option<String>>;
+
+// Format: (group_by= parameter value, groups a player belongs to)
+// Players with several positions count towards each of their positions' groups
+const GROUP_BY_DEFS: [(&str, GroupKeys); 4] = [
+    ("club", |p| vec![p.club_name.clone()]),
+    ("position", |p| p.positions.iter().cloned().map(Some).collect()),
+    ("nationality", |p| vec![p.nationality.clone()]),
+    ("age_bucket", |p| vec![p.age.map(|age| age_bucket(age).to_owned())]),
+];
+
+// Format: (upper age bound, exclusive; bucket label); older players fall into OLDEST_AGE_BUCKET
+const AGE_BUCKETS: [(i64, &str); 4] = [(21, "0-20"), (25, "21-24"), (29, "25-28"), (33, "29-32")];
+const OLDEST_AGE_BUCKET: &str = "33+";
+
+pub fn group_by_names() -> Vec<&'static str> {
+    GROUP_BY_DEFS.iter().map(|(name, _)| *name).collect()
+}
+
+fn age_bucket(age: i64) -> &'static str {
+    AGE_BUCKETS
+        .iter()
+        .find(|(upper, _)| age < *upper)
+        .map_or(OLDEST_AGE_BUCKET, |(_, label)| label)
+}
+
+#[derive(Copy, Clone)]
+enum AggregateFn {
+    Sum,
+    Avg,
+    Min,
+    Max,
+    Median,
+}
+const AGGREGATE_FNS: [AggregateFn; 5] = [
+    AggregateFn::Sum,
+    AggregateFn::Avg,
+    AggregateFn::Min,
+    AggregateFn::Max,
+    AggregateFn::Median,
+];
+
+pub fn aggregate_fn_names() -> Vec<&'static str> {
+    AGGREGATE_FNS.iter().map(|f| f.name()).collect()
+}
+
+impl AggregateFn {
+    fn from_name(name: &str) -> Option<Self> {
+        AGGREGATE_FNS.into_iter().find(|f| f.name() == name)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            AggregateFn::Sum => "sum",
+            AggregateFn::Avg => "avg",
+            AggregateFn::Min => "min",
+            AggregateFn::Max => "max",
+            AggregateFn::Median => "median",
+        }
+    }
+
+    fn apply(&self, values: &[f64]) -> Option<f64> {
+        // Missing values are filtered out beforehand; a group with no values has no aggregate
+        if values.is_empty() {
+            return None;
+        }
+        Some(match self {
+            AggregateFn::Sum => values.iter().sum(),
+            AggregateFn::Avg => mean(values),
+            AggregateFn::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            AggregateFn::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            AggregateFn::Median => quantile(values, 0.5),
+        })
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn quantile(values: &[f64], q: f64) -> f64 {
+    // Linear interpolation between closest ranks; values must be non-empty
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let rank = q * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+pub fn aggregate<S: PlayerStore + ?Sized>(
+    store: &S,
+    group_by: String,
+    stats: Vec<String>,
+    function: String,
+) -> Result<ResultTable, DbError> {
+    let groups_of = GROUP_BY_DEFS
+        .iter()
+        .find(|(name, _)| *name == group_by)
+        .map(|(_, groups_of)| *groups_of)
+        .ok_or(DbError::InvalidArgument(format!(
+            "Invalid group_by: {} (expected club|position|nationality|age_bucket)",
+            group_by
+        )))?;
+    let function = AggregateFn::from_name(&function).ok_or(DbError::InvalidArgument(format!(
+        "Invalid fn: {} (expected sum|avg|min|max|median)",
+        function
+    )))?;
+    let stats: Vec<&'static str> = stats
+        .iter()
+        .map(|s| DB::validate_statistic(s))
+        .collect::<Result<_, DbError>>()?;
+    if stats.is_empty() {
+        return Err(DbError::InvalidArgument(
+            "aggregate requires at least one statistic".to_owned(),
+        ));
+    }
+    // Group -> (player count, non-missing values per statistic)
+    let mut groups: BTreeMap<Option<String>, (i64, Vec<Vec<f64>>)> = BTreeMap::new();
+    for player in store.player_statistics(None, None, &stats)? {
+        for key in groups_of(&player) {
+            let group = groups.entry(key).or_insert((0, vec![vec![]; stats.len()]));
+            group.0 += 1;
+            for (values, value) in group.1.iter_mut().zip(&player.values) {
+                values.extend(value);
+            }
+        }
+    }
+    let mut column_names = vec![group_by.clone(), "players".to_owned()];
+    column_names.extend(stats.iter().map(|s| format!("{}_{}", function.name(), s)));
+    let rows: Vec<Vec<Value>> = groups
+        .into_iter()
+        .map(|(key, (count, values))| {
+            let mut row = vec![key.map_or(Value::Null, Value::Text), Value::Integer(count)];
+            row.extend(
+                values
+                    .iter()
+                    .map(|v| function.apply(v).map_or(Value::Null, Value::Real)),
+            );
+            row
+        })
+        .collect();
+    Ok(ResultTable::new(&column_names, rows))
+}
+
+const DEFAULT_DISTRIBUTION_BINS: usize = 10;
+const MAX_DISTRIBUTION_BINS: usize = 100;
+
+// Columns of the distribution table; summary rows leave lower and upper empty, and histogram rows are named "bin"
+// with value being the number of players in [lower, upper) ([lower, upper] for the last bin)
+pub const DISTRIBUTION_COLUMNS: [&str; 4] = ["measure", "lower", "upper", "value"];
+pub const DISTRIBUTION_BIN_MEASURE: &str = "bin";
+
+fn stddev(values: &[f64]) -> Option<f64> {
+    // Sample standard deviation, undefined for fewer than two values
+    if values.len() < 2 {
+        return None;
+    }
+    let mean = mean(values);
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+    Some(variance.sqrt())
+}
+
+fn histogram(values: &[f64], bins: usize) -> Vec<(f64, f64, i64)> {
+    // Equal-width bins spanning [min, max]; values must be non-empty
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if min == max {
+        return vec![(min, max, values.len() as i64)];
+    }
+    let width = (max - min) / bins as f64;
+    let mut counts = vec![0; bins];
+    for v in values {
+        counts[(((v - min) / width) as usize).min(bins - 1)] += 1;
+    }
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| (min + width * i as f64, min + width * (i + 1) as f64, count))
+        .collect()
+}
+
+pub fn distribution<S: PlayerStore + ?Sized>(
+    store: &S,
+    statistic: String,
+    position: Option<String>,
+    club: Option<String>,
+    bins: Option<String>,
+) -> Result<ResultTable, DbError> {
+    let statistic = DB::validate_statistic(&statistic)?;
+    let bins = match bins {
+        Some(b) => match b.parse::<usize>() {
+            Ok(b) if (1..=MAX_DISTRIBUTION_BINS).contains(&b) => b,
+            _ => {
+                return Err(DbError::InvalidArgument(format!(
+                    "Invalid bins: {} (expected 1-{})",
+                    b, MAX_DISTRIBUTION_BINS
+                )))
+            }
+        },
+        None => DEFAULT_DISTRIBUTION_BINS,
+    };
+    let values: Vec<f64> = store
+        .player_statistics(club.as_deref(), position.as_deref(), &[statistic])?
+        .into_iter()
+        .filter_map(|p| p.values[0])
+        .collect();
+    let summary_row = |measure: &str, value: Option<f64>| {
+        vec![
+            Value::Text(measure.to_owned()),
+            Value::Null,
+            Value::Null,
+            value.map_or(Value::Null, Value::Real),
+        ]
+    };
+    let mut rows = vec![vec![
+        Value::Text("count".to_owned()),
+        Value::Null,
+        Value::Null,
+        Value::Integer(values.len() as i64),
+    ]];
+    if values.is_empty() {
+        return Ok(ResultTable::new(&DISTRIBUTION_COLUMNS, rows));
+    }
+    rows.push(summary_row("mean", Some(mean(&values))));
+    rows.push(summary_row("stddev", stddev(&values)));
+    for (measure, q) in [("min", 0.0), ("q1", 0.25), ("median", 0.5), ("q3", 0.75), ("max", 1.0)] {
+        rows.push(summary_row(measure, Some(quantile(&values, q))));
+    }
+    rows.extend(histogram(&values, bins).into_iter().map(|(lower, upper, count)| {
+        vec![
+            Value::Text(DISTRIBUTION_BIN_MEASURE.to_owned()),
+            Value::Real(lower),
+            Value::Real(upper),
+            Value::Integer(count),
+        ]
+    }));
+    Ok(ResultTable::new(&DISTRIBUTION_COLUMNS, rows))
+}
+
+const DEFAULT_CORRELATION_PAIRS: usize = 10;
+// view= parameter values of correlations
+const CORRELATION_VIEWS: [&str; 2] = ["matrix", "pairs"];
+
+fn pearson(xs: &[f64], ys: &[f64]) -> Option<f64> {
+    // Undefined for fewer than two observations or if either variable is constant
+    if xs.len() < 2 {
+        return None;
+    }
+    let (mean_x, mean_y) = (mean(xs), mean(ys));
+    let (mut covariance, mut variance_x, mut variance_y) = (0.0, 0.0, 0.0);
+    for (x, y) in xs.iter().zip(ys) {
+        covariance += (x - mean_x) * (y - mean_y);
+        variance_x += (x - mean_x).powi(2);
+        variance_y += (y - mean_y).powi(2);
+    }
+    if variance_x == 0.0 || variance_y == 0.0 {
+        return None;
+    }
+    Some(covariance / (variance_x * variance_y).sqrt())
+}
+
+fn ranks(values: &[f64]) -> Vec<f64> {
+    // 1-based ranks, tied values sharing the average of the ranks they span
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|a, b| values[*a].total_cmp(&values[*b]));
+    let mut ranks = vec![0.0; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]] == values[order[start]] {
+            end += 1;
+        }
+        let average_rank = (start + end + 1) as f64 / 2.0;
+        for i in &order[start..end] {
+            ranks[*i] = average_rank;
+        }
+        start = end;
+    }
+    ranks
+}
+
+#[derive(Copy, Clone)]
+enum CorrelationMethod {
+    Pearson,
+    Spearman,
+}
+const CORRELATION_METHODS: [CorrelationMethod; 2] = [CorrelationMethod::Pearson, CorrelationMethod::Spearman];
+
+pub fn correlation_method_names() -> Vec<&'static str> {
+    CORRELATION_METHODS.iter().map(|m| m.name()).collect()
+}
+
+pub fn correlation_view_names() -> Vec<&'static str> {
+    CORRELATION_VIEWS.to_vec()
+}
+
+impl CorrelationMethod {
+    fn from_name(name: &str) -> Option<Self> {
+        CORRELATION_METHODS.into_iter().find(|m| m.name() == name)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            CorrelationMethod::Pearson => "pearson",
+            CorrelationMethod::Spearman => "spearman",
+        }
+    }
+
+    fn correlate(&self, xs: &[f64], ys: &[f64]) -> Option<f64> {
+        match self {
+            CorrelationMethod::Pearson => pearson(xs, ys),
+            CorrelationMethod::Spearman => pearson(&ranks(xs), &ranks(ys)),
+        }
+    }
+}
+
+pub fn correlations<S: PlayerStore + ?Sized>(
+    store: &S,
+    statistics: Vec<String>,
+    method: Option<String>,
+    position: Option<String>,
+    club: Option<String>,
+    view: Option<String>,
+    top: Option<String>,
+) -> Result<ResultTable, DbError> {
+    // view=matrix (default) gives the full correlation matrix; view=pairs gives the top strongest pairs,
+    // ranked by absolute correlation
+    let method = method.unwrap_or("pearson".to_owned());
+    let method = CorrelationMethod::from_name(&method).ok_or(DbError::InvalidArgument(format!(
+        "Invalid method: {} (expected pearson|spearman)",
+        method
+    )))?;
+    let stats: Vec<&'static str> = if statistics.len() == 1 && statistics[0] == "all" {
+        super::STATISTICS_COLUMNS.to_vec()
+    } else {
+        statistics
+            .iter()
+            .map(|s| DB::validate_statistic(s))
+            .collect::<Result<_, DbError>>()?
+    };
+    if stats.len() < 2 {
+        return Err(DbError::InvalidArgument(
+            "correlations requires at least two statistics".to_owned(),
+        ));
+    }
+    let top = match top {
+        Some(t) => t
+            .parse::<usize>()
+            .map_err(|_| DbError::InvalidArgument(format!("Invalid top: {}", t)))?,
+        None => DEFAULT_CORRELATION_PAIRS,
+    };
+    let players: Vec<Vec<Option<f64>>> = store
+        .player_statistics(club.as_deref(), position.as_deref(), &stats)?
+        .into_iter()
+        .map(|p| p.values)
+        .collect();
+    // Each pair only uses the players that have both statistics (pairwise deletion of missing values)
+    let correlate = |a: usize, b: usize| {
+        let (xs, ys): (Vec<f64>, Vec<f64>) = players.iter().filter_map(|p| Some((p[a]?, p[b]?))).unzip();
+        (method.correlate(&xs, &ys), xs.len() as i64)
+    };
+    match view.as_deref().unwrap_or("matrix") {
+        "matrix" => {
+            let mut column_names = vec!["statistic"];
+            column_names.extend(&stats);
+            let rows: Vec<Vec<Value>> = (0..stats.len())
+                .map(|a| {
+                    let mut row = vec![Value::Text(stats[a].to_owned())];
+                    row.extend((0..stats.len()).map(|b| correlate(a, b).0.map_or(Value::Null, Value::Real)));
+                    row
+                })
+                .collect();
+            Ok(ResultTable::new(&column_names, rows))
+        }
+        "pairs" => {
+            let mut pairs: Vec<(usize, usize, f64, i64)> = vec![];
+            for a in 0..stats.len() {
+                for b in a + 1..stats.len() {
+                    if let (Some(r), players) = correlate(a, b) {
+                        pairs.push((a, b, r, players));
+                    }
+                }
+            }
+            pairs.sort_by(|x, y| y.2.abs().total_cmp(&x.2.abs()));
+            pairs.truncate(top);
+            let rows: Vec<Vec<Value>> = pairs
+                .into_iter()
+                .enumerate()
+                .map(|(i, (a, b, r, players))| {
+                    vec![
+                        Value::Integer(i as i64 + 1),
+                        Value::Text(stats[a].to_owned()),
+                        Value::Text(stats[b].to_owned()),
+                        Value::Real(r),
+                        Value::Integer(players),
+                    ]
+                })
+                .collect();
+            Ok(ResultTable::new(
+                &["rank", "statistic_a", "statistic_b", "correlation", "players"],
+                rows,
+            ))
+        }
+        v => Err(DbError::InvalidArgument(format!(
+            "Invalid view: {} (expected matrix|pairs)",
+            v
+        ))),
+    }
+}
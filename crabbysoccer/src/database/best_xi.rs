@@ -0,0 +1,270 @@
+use super::{format::ResultTable, DbError, PlayerStore, DB};
+use rusqlite::types::Value;
+
+// Cost of assigning a player to a slot they are not eligible for; large enough that
+// the assignment only ever uses it when no eligible player is left
+const INELIGIBLE_COST: f64 = 1e12;
+// Cost of assigning an eligible player whose score is missing (NULL); they are only picked
+// once every eligible player with a score has been used
+const UNSCORED_COST: f64 = 1e9;
+
+struct Candidate {
+    id: i64,
+    name: String,
+    club_name: String,
+    positions: Vec<String>,
+    score: Option<f64>,
+}
+
+fn parse_formation(formation: &str) -> Result<Vec<&'static str>, DbError> {
+    // Formations are given outfield-only, from the back: "4-3-3", "4-2-3-1", etc.
+    // The first line is defenders, the last is forwards, everything in between is midfield
+    let lines: Vec<usize> = formation
+        .split('-')
+        .map(|e| e.trim().parse::<usize>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| DbError::InvalidArgument(format!("Malformed formation: {}", formation)))?;
+    if lines.len() < 3 {
+        return Err(DbError::InvalidArgument(format!(
+            "Formation must have at least 3 lines: {}",
+            formation
+        )));
+    }
+    if lines.contains(&0) {
+        return Err(DbError::InvalidArgument(format!(
+            "Every line of a formation must have at least one player: {}",
+            formation
+        )));
+    }
+    if lines.iter().sum::<usize>() != 10 {
+        return Err(DbError::InvalidArgument(format!(
+            "Formation must have 10 outfield players: {}",
+            formation
+        )));
+    }
+    let midfielders: usize = lines[1..lines.len() - 1].iter().sum();
+    let mut slots = vec!["Goalkeeper"];
+    slots.extend(std::iter::repeat_n("Defender", lines[0]));
+    slots.extend(std::iter::repeat_n("Midfielder", midfielders));
+    slots.extend(std::iter::repeat_n("Forward", lines[lines.len() - 1]));
+    Ok(slots)
+}
+
+fn hungarian(cost: &[Vec<f64>]) -> Vec<usize> {
+    // Minimum-cost assignment of n rows to m columns (n <= m) via the Hungarian algorithm
+    // using potentials, O(n^2 * m). Returns the column assigned to each row.
+    let n = cost.len();
+    let m = cost[0].len();
+    let mut u = vec![0.0; n + 1];
+    let mut v = vec![0.0; m + 1];
+    // p[j] = row (1-indexed) currently matched to column j; column 0 is a sentinel
+    let mut p = vec![0usize; m + 1];
+    let mut way = vec![0usize; m + 1];
+    for i in 1..=n {
+        p[0] = i;
+        let mut j0 = 0;
+        let mut minv = vec![f64::INFINITY; m + 1];
+        let mut used = vec![false; m + 1];
+        loop {
+            used[j0] = true;
+            let i0 = p[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0;
+            for j in 1..=m {
+                if !used[j] {
+                    let cur = cost[i0 - 1][j - 1] - u[i0] - v[j];
+                    if cur < minv[j] {
+                        minv[j] = cur;
+                        way[j] = j0;
+                    }
+                    if minv[j] < delta {
+                        delta = minv[j];
+                        j1 = j;
+                    }
+                }
+            }
+            for j in 0..=m {
+                if used[j] {
+                    u[p[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    minv[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if p[j0] == 0 {
+                break;
+            }
+        }
+        loop {
+            let j1 = way[j0];
+            p[j0] = p[j1];
+            j0 = j1;
+            if j0 == 0 {
+                break;
+            }
+        }
+    }
+    let mut assignment = vec![0usize; n];
+    for j in 1..=m {
+        if p[j] != 0 {
+            assignment[p[j] - 1] = j - 1;
+        }
+    }
+    assignment
+}
+
+fn composite_score(composite: &[(&str, f64)], values: &[Option<f64>]) -> Option<f64> {
+    // Missing terms are skipped; the composite is only missing if every term is missing
+    if values.iter().all(Option::is_none) {
+        return None;
+    }
+    Some(
+        composite
+            .iter()
+            .zip(values)
+            .map(|((_, weight), value)| weight * value.unwrap_or(0.0))
+            .sum(),
+    )
+}
+
+pub fn best_xi<S: PlayerStore + ?Sized>(
+    store: &S,
+    formation: String,
+    club: Option<String>,
+    score: Vec<String>,
+) -> Result<ResultTable, DbError> {
+    let slots = parse_formation(&formation)?;
+    let composite = DB::parse_composite(&score)?;
+    let stats: Vec<&'static str> = composite.iter().map(|(stat, _)| *stat).collect();
+    let candidates: Vec<Candidate> = store
+        .player_statistics(club.as_deref(), None, &stats)?
+        .into_iter()
+        .map(|p| Candidate {
+            score: composite_score(&composite, &p.values),
+            id: p.id,
+            name: p.name,
+            club_name: p.club_name.unwrap_or_default(),
+            positions: p.positions,
+        })
+        .collect();
+    if candidates.len() < slots.len() {
+        return Err(DbError::InvalidArgument(format!(
+            "Not enough players to fill formation {} ({} available)",
+            formation,
+            candidates.len()
+        )));
+    }
+    let assignment = assign_slots(&slots, &candidates)?;
+    let mut total = 0.0;
+    let mut rows: Vec<Vec<Value>> = vec![];
+    for (slot_idx, (slot, candidate_idx)) in slots.iter().zip(assignment).enumerate() {
+        let c = &candidates[candidate_idx];
+        total += c.score.unwrap_or(0.0);
+        rows.push(vec![
+            Value::Integer(slot_idx as i64 + 1),
+            Value::Text(slot.to_string()),
+            Value::Integer(c.id),
+            Value::Text(c.name.clone()),
+            Value::Text(c.club_name.clone()),
+            c.score.map_or(Value::Null, Value::Real),
+        ]);
+    }
+    rows.push(vec![
+        Value::Null,
+        Value::Text("Total".to_owned()),
+        Value::Null,
+        Value::Null,
+        Value::Null,
+        Value::Real(total),
+    ]);
+    Ok(ResultTable::new(
+        &["slot", "position", "id", "name", "club_name", "score"],
+        rows,
+    ))
+}
+
+fn assign_slots(slots: &[&str], candidates: &[Candidate]) -> Result<Vec<usize>, DbError> {
+    // Returns the candidate picked for each slot. There must be at least as many candidates as slots
+    // Maximize total score <=> minimize negated score
+    let cost: Vec<Vec<f64>> = slots
+        .iter()
+        .map(|slot| {
+            candidates
+                .iter()
+                .map(|c| {
+                    if c.positions.iter().any(|p| p == slot) {
+                        c.score.map_or(UNSCORED_COST, |s| -s)
+                    } else {
+                        INELIGIBLE_COST
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    let assignment = hungarian(&cost);
+    for (slot_idx, (slot, candidate_idx)) in slots.iter().zip(&assignment).enumerate() {
+        if cost[slot_idx][*candidate_idx] >= INELIGIBLE_COST {
+            return Err(DbError::InvalidArgument(format!(
+                "Not enough eligible players for position: {}",
+                slot
+            )));
+        }
+    }
+    Ok(assignment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(id: i64, positions: &[&str], score: f64) -> Candidate {
+        Candidate {
+            id,
+            name: format!("Player {}", id),
+            club_name: String::new(),
+            positions: positions.iter().map(|p| p.to_string()).collect(),
+            score: Some(score),
+        }
+    }
+
+    #[test]
+    fn hungarian_finds_optimal_assignment() {
+        // Greedily taking the cheapest cell (0, 0) first would cost 1 + 10 + 3 = 14; the optimum is 2 + 2 + 3 = 7
+        let cost = vec![vec![1.0, 2.0, 9.0], vec![2.0, 10.0, 9.0], vec![9.0, 9.0, 3.0]];
+        assert_eq!(hungarian(&cost), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn hungarian_leaves_extra_columns_unassigned() {
+        let cost = vec![vec![4.0, 1.0, 3.0, 8.0], vec![2.0, 0.0, 5.0, 9.0]];
+        assert_eq!(hungarian(&cost), vec![1, 0]);
+    }
+
+    #[test]
+    fn parse_formation_rejects_empty_lines() {
+        assert_eq!(parse_formation("4-3-3").unwrap().len(), 11);
+        for formation in ["10-0-0", "0-5-5", "4-0-3-3", "4-3-x"] {
+            assert!(parse_formation(formation).is_err(), "{}", formation);
+        }
+    }
+
+    #[test]
+    fn assign_slots_picks_best_eligible_players() {
+        let candidates = vec![
+            candidate(1, &["Defender"], 3.0),
+            candidate(2, &["Defender", "Forward"], 5.0),
+            candidate(3, &["Forward"], 4.0),
+        ];
+        assert_eq!(assign_slots(&["Defender", "Forward"], &candidates).unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn assign_slots_rejects_slot_without_eligible_player() {
+        let candidates = vec![candidate(1, &["Defender"], 3.0), candidate(2, &["Defender"], 5.0)];
+        assert!(matches!(
+            assign_slots(&["Goalkeeper", "Defender"], &candidates),
+            Err(DbError::InvalidArgument(_))
+        ));
+    }
+}
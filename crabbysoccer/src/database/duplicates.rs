@@ -0,0 +1,157 @@
+use super::format::{OutputFormat, ResultTable};
+use rusqlite::types::Value;
+use std::collections::HashMap;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+// Environment variable choosing what the importer does with duplicate csv rows: "merge" (default) or "keep"
+pub const DUPLICATES_ENV_VAR: &str = "CRABBYSOCCER_DUPLICATES";
+
+// csv columns two rows must agree on (after normalize_name, for the text columns) to be considered the same player
+const MATCH_COLUMNS: [&str; 4] = ["Name", "Club", "Jersey Number", "Nationality"];
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum DuplicatePolicy {
+    // Each duplicate row is folded into the first row for the same player, filling in that row's blank cells
+    Merge,
+    // Every row is imported as its own player; duplicates are only reported
+    Keep,
+}
+impl DuplicatePolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "merge" => Some(DuplicatePolicy::Merge),
+            "keep" => Some(DuplicatePolicy::Keep),
+            _ => None,
+        }
+    }
+}
+
+pub fn normalize_name(name: &str) -> String {
+    // NFKD splits accented letters into base letter + combining mark, so dropping the marks matches e.g.
+    // "Rúnarsson" with "Runarsson"; whitespace runs collapse to one space and case is folded
+    name.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+struct Duplicate {
+    // Indices into the csv data rows
+    kept: usize,
+    duplicate: usize,
+    // Columns where both rows have a value and the values differ; the kept row's value wins when merging
+    conflicts: Vec<String>,
+}
+
+fn find_duplicates(header: &[String], data: &[Vec<String>]) -> Vec<Duplicate> {
+    // Columns missing from the csv match as blank
+    let match_indices: Vec<Option<usize>> = MATCH_COLUMNS
+        .iter()
+        .map(|c| header.iter().position(|h| h == c))
+        .collect();
+    let mut first_seen: HashMap<Vec<String>, usize> = HashMap::new();
+    let mut duplicates: Vec<Duplicate> = vec![];
+    for (i, row) in data.iter().enumerate() {
+        let key: Vec<String> = match_indices
+            .iter()
+            .map(|idx| idx.map_or(String::new(), |idx| normalize_name(&row[idx])))
+            .collect();
+        match first_seen.get(&key) {
+            Some(&kept) => duplicates.push(Duplicate {
+                kept,
+                duplicate: i,
+                conflicts: header
+                    .iter()
+                    .enumerate()
+                    .filter(|(c, name)| {
+                        let (a, b) = (data[kept][*c].trim(), row[*c].trim());
+                        *name != "Name" && !a.is_empty() && !b.is_empty() && a != b
+                    })
+                    .map(|(_, name)| name.clone())
+                    .collect(),
+            }),
+            None => {
+                first_seen.insert(key, i);
+            }
+        }
+    }
+    duplicates
+}
+
+fn merge_report(
+    header: &[String],
+    data: &[Vec<String>],
+    duplicates: &[Duplicate],
+    policy: DuplicatePolicy,
+) -> ResultTable {
+    let cell = |row: usize, column: &str| match header.iter().position(|h| h == column) {
+        Some(idx) => Value::Text(data[row][idx].trim().to_owned()),
+        None => Value::Null,
+    };
+    let rows: Vec<Vec<Value>> = duplicates
+        .iter()
+        .map(|d| {
+            // csv line numbers: line 1 is the header
+            vec![
+                Value::Integer(d.duplicate as i64 + 2),
+                Value::Integer(d.kept as i64 + 2),
+                cell(d.duplicate, "Name"),
+                cell(d.kept, "Name"),
+                cell(d.kept, "Club"),
+                Value::Text(d.conflicts.join("/")),
+                Value::Text(
+                    match policy {
+                        DuplicatePolicy::Merge => "merged",
+                        DuplicatePolicy::Keep => "kept",
+                    }
+                    .to_owned(),
+                ),
+            ]
+        })
+        .collect();
+    ResultTable::new(
+        &[
+            "line",
+            "duplicate_of",
+            "name",
+            "kept_name",
+            "club",
+            "conflicts",
+            "action",
+        ],
+        rows,
+    )
+}
+
+pub fn resolve_duplicates(header: &[String], mut data: Vec<Vec<String>>, policy: DuplicatePolicy) -> Vec<Vec<String>> {
+    // Prints the merge report, then applies it if policy is Merge
+    let duplicates = find_duplicates(header, &data);
+    println!("Found {} duplicate row(s) in csv", duplicates.len());
+    if duplicates.is_empty() {
+        return data;
+    }
+    println!(
+        "{}",
+        OutputFormat::Text.render(&merge_report(header, &data, &duplicates, policy))
+    );
+    if policy == DuplicatePolicy::Keep {
+        return data;
+    }
+    for d in &duplicates {
+        let duplicate = data[d.duplicate].clone();
+        for (cell, value) in data[d.kept].iter_mut().zip(duplicate) {
+            if cell.trim().is_empty() {
+                *cell = value;
+            }
+        }
+    }
+    let mut i = 0;
+    data.retain(|_| {
+        i += 1;
+        !duplicates.iter().any(|d| d.duplicate == i - 1)
+    });
+    data
+}
@@ -0,0 +1,82 @@
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum DbError {
+    // A parameter value was rejected, e.g. an unknown statistic or a malformed formation
+    InvalidArgument(String),
+    // A parameter referred to something that does not exist, e.g. an unknown player id
+    NotFound(String),
+    // The operation is not available on the configured storage backend, e.g. explain on the in-memory backend
+    Unsupported(String),
+    Sqlite(rusqlite::Error),
+}
+impl DbError {
+    pub fn kind(&self) -> &'static str {
+        // Stable identifier sent to clients alongside the message
+        match self {
+            DbError::InvalidArgument(_) => "invalid-argument",
+            DbError::NotFound(_) => "not-found",
+            DbError::Unsupported(_) => "unsupported",
+            DbError::Sqlite(_) => "database-error",
+        }
+    }
+}
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DbError::InvalidArgument(message) | DbError::NotFound(message) | DbError::Unsupported(message) => {
+                write!(f, "{}", message)
+            }
+            DbError::Sqlite(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl std::error::Error for DbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DbError::Sqlite(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<rusqlite::Error> for DbError {
+    fn from(value: rusqlite::Error) -> Self {
+        DbError::Sqlite(value)
+    }
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io { path: String, source: io::Error },
+    // The csv's contents could not be mapped onto the schema, e.g. an unknown column or a short row
+    MalformedCsv(String),
+    Db(DbError),
+}
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Io { path, source } => write!(f, "Unable to read {}: {}", path, source),
+            ImportError::MalformedCsv(message) => write!(f, "Malformed csv: {}", message),
+            ImportError::Db(e) => write!(f, "Import failed: {}", e),
+        }
+    }
+}
+impl std::error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImportError::Io { source, .. } => Some(source),
+            ImportError::MalformedCsv(_) => None,
+            ImportError::Db(e) => Some(e),
+        }
+    }
+}
+impl From<DbError> for ImportError {
+    fn from(value: DbError) -> Self {
+        ImportError::Db(value)
+    }
+}
+impl From<rusqlite::Error> for ImportError {
+    fn from(value: rusqlite::Error) -> Self {
+        ImportError::Db(DbError::Sqlite(value))
+    }
+}
@@ -0,0 +1,182 @@
+use super::{format::ResultTable, DbError, PlayerStore, DB};
+use rusqlite::types::Value;
+use std::{collections::HashMap, fs::read_to_string, io::ErrorKind, path::Path};
+
+// Directory containing scoring ruleset files (<ruleset name>.rules); see rulesets/default.rules for the format
+const RULESETS_DIR: &str = "rulesets";
+const ALL_POSITIONS_SECTION: &str = "*";
+const POSITIONS: [&str; 4] = ["Forward", "Midfielder", "Defender", "Goalkeeper"];
+
+pub struct Ruleset {
+    // Section ("*" or a position name) -> (statistic, points per unit) pairs
+    sections: HashMap<String, Vec<(&'static str, f64)>>,
+}
+impl Ruleset {
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let mut sections: HashMap<String, Vec<(&'static str, f64)>> = HashMap::new();
+        let mut current: Option<String> = None;
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let section = section.trim();
+                if section != ALL_POSITIONS_SECTION && !POSITIONS.contains(&section) {
+                    return Err(format!("Line {}: unknown position section [{}]", i + 1, section));
+                }
+                sections.entry(section.to_owned()).or_default();
+                current = Some(section.to_owned());
+                continue;
+            }
+            let section = match &current {
+                Some(s) => s,
+                None => return Err(format!("Line {}: rule outside of a [position] section", i + 1)),
+            };
+            let (stat, points) = line
+                .split_once('=')
+                .ok_or(format!("Line {}: expected <statistic> = <points>", i + 1))?;
+            let stat = DB::validate_statistic(stat).map_err(|e| format!("Line {}: {}", i + 1, e))?;
+            let points = points
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| format!("Line {}: invalid points value: {}", i + 1, points.trim()))?;
+            let rules = sections.get_mut(section).unwrap();
+            match rules.iter_mut().find(|(s, _)| *s == stat) {
+                Some(rule) => rule.1 = points,
+                None => rules.push((stat, points)),
+            }
+        }
+        Ok(Self { sections })
+    }
+
+    pub fn load(name: &str) -> Result<Self, DbError> {
+        // Ruleset names map directly onto file names, so keep them from escaping RULESETS_DIR
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(DbError::InvalidArgument(format!("Invalid ruleset name: {}", name)));
+        }
+        let path = Path::new(RULESETS_DIR).join(format!("{}.rules", name));
+        let contents = read_to_string(&path).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => DbError::NotFound(format!("No such ruleset: {}", name)),
+            _ => DbError::InvalidArgument(format!("Unable to read ruleset {}: {}", name, e)),
+        })?;
+        Ruleset::parse(&contents).map_err(|e| DbError::InvalidArgument(format!("Ruleset {}: {}", name, e)))
+    }
+
+    fn statistics(&self) -> Vec<&'static str> {
+        let mut stats: Vec<&'static str> = vec![];
+        for (stat, _) in self.sections.values().flatten() {
+            if !stats.contains(stat) {
+                stats.push(stat);
+            }
+        }
+        stats
+    }
+
+    fn rules_for(&self, position: &str) -> Vec<(&'static str, f64)> {
+        // Position-specific rules take precedence over "*" rules for the same statistic
+        let mut rules: Vec<(&'static str, f64)> = self.sections.get(position).cloned().unwrap_or_default();
+        if let Some(general) = self.sections.get(ALL_POSITIONS_SECTION) {
+            for (stat, points) in general {
+                if !rules.iter().any(|(s, _)| s == stat) {
+                    rules.push((stat, *points));
+                }
+            }
+        }
+        rules
+    }
+
+    fn score(&self, position: &str, values: &HashMap<&str, f64>) -> (f64, Vec<String>) {
+        // Returns total points and a breakdown entry per contributing statistic
+        let mut total = 0.0;
+        let mut breakdown = vec![];
+        for (stat, points) in self.rules_for(position) {
+            // Missing (NULL) statistics score nothing
+            let value = match values.get(stat) {
+                Some(v) => *v,
+                None => continue,
+            };
+            let contribution = value * points;
+            if contribution != 0.0 {
+                total += contribution;
+                breakdown.push(format!("{}: {} x {} = {:.2}", stat, value, points, contribution));
+            }
+        }
+        (total, breakdown)
+    }
+}
+
+struct FantasyRow {
+    id: i64,
+    name: String,
+    club_name: String,
+    position: String,
+    points: f64,
+    breakdown: Vec<String>,
+}
+
+pub fn fantasy_points<S: PlayerStore + ?Sized>(
+    store: &S,
+    ruleset: Option<String>,
+    club: Option<String>,
+    position: Option<String>,
+    limit: Option<String>,
+) -> Result<ResultTable, DbError> {
+    let ruleset = Ruleset::load(ruleset.as_deref().unwrap_or("default"))?;
+    let limit = match limit {
+        Some(l) => Some(
+            l.parse::<usize>()
+                .map_err(|_| DbError::InvalidArgument(format!("Invalid limit: {}", l)))?,
+        ),
+        None => None,
+    };
+    let stats = ruleset.statistics();
+    let mut rows: Vec<FantasyRow> = store
+        .player_statistics(club.as_deref(), position.as_deref(), &stats)?
+        .into_iter()
+        .filter_map(|p| {
+            let values: HashMap<&str, f64> = stats
+                .iter()
+                .zip(&p.values)
+                .filter_map(|(stat, v)| v.map(|v| (*stat, v)))
+                .collect();
+            // Players listed under several positions are scored under whichever is worth the most
+            let (position, (points, breakdown)) = p
+                .positions
+                .iter()
+                .map(|position| (position.to_owned(), ruleset.score(position, &values)))
+                .max_by(|a, b| a.1 .0.total_cmp(&b.1 .0))?;
+            Some(FantasyRow {
+                id: p.id,
+                name: p.name,
+                club_name: p.club_name.unwrap_or_default(),
+                position,
+                points,
+                breakdown,
+            })
+        })
+        .collect();
+    rows.sort_by(|a, b| b.points.total_cmp(&a.points));
+    if let Some(limit) = limit {
+        rows.truncate(limit);
+    }
+    let rows: Vec<Vec<Value>> = rows
+        .into_iter()
+        .enumerate()
+        .map(|(i, r)| {
+            vec![
+                Value::Integer(i as i64 + 1),
+                Value::Integer(r.id),
+                Value::Text(r.name),
+                Value::Text(r.club_name),
+                Value::Text(r.position),
+                Value::Real((r.points * 100.0).round() / 100.0),
+                Value::Text(r.breakdown.join("; ")),
+            ]
+        })
+        .collect();
+    Ok(ResultTable::new(
+        &["rank", "id", "name", "club_name", "position", "points", "breakdown"],
+        rows,
+    ))
+}
@@ -0,0 +1,424 @@
+use super::MISSING_VALUE;
+use rusqlite::types::Value;
+
+// Query result, kept typed until it is rendered by a ResultFormatter
+#[derive(Clone)]
+pub struct ResultTable {
+    pub column_names: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+impl ResultTable {
+    pub fn new<T: ToString>(column_names: &[T], rows: Vec<Vec<Value>>) -> Self {
+        Self {
+            column_names: column_names.iter().map(|e| e.to_string()).collect(),
+            rows,
+        }
+    }
+
+    pub fn retain_columns(&mut self, keep: &[bool]) {
+        // Drops every column i for which keep[i] is false
+        let mut i = 0;
+        self.column_names.retain(|_| {
+            i += 1;
+            keep[i - 1]
+        });
+        for row in self.rows.iter_mut() {
+            let mut i = 0;
+            row.retain(|_| {
+                i += 1;
+                keep[i - 1]
+            });
+        }
+    }
+}
+
+pub trait ResultFormatter {
+    fn format(&self, table: &ResultTable) -> String;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Csv,
+    Json,
+    Ndjson,
+    Markdown,
+    Html,
+}
+
+// Format: (format= parameter value, media type for the Accept header, OutputFormat::{})
+const OUTPUT_FORMAT_DEFS: [(&str, &str, OutputFormat); 6] = [
+    ("text", "text/plain", OutputFormat::Text),
+    ("csv", "text/csv", OutputFormat::Csv),
+    ("json", "application/json", OutputFormat::Json),
+    ("ndjson", "application/x-ndjson", OutputFormat::Ndjson),
+    ("markdown", "text/markdown", OutputFormat::Markdown),
+    ("html", "text/html", OutputFormat::Html),
+];
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_lowercase();
+        OUTPUT_FORMAT_DEFS.iter().find(|(n, _, _)| *n == name).map(|e| e.2)
+    }
+
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        // Picks the media type we can produce with the highest q= weight (1 if omitted), the first listed on ties.
+        // Media types with q=0 are refused; wildcards such as */* are not matched, leaving the default to the caller
+        let mut candidates: Vec<(f64, Self)> = accept
+            .split(',')
+            .filter_map(|media_range| {
+                let mut params = media_range.split(';');
+                let media_type = params.next().unwrap_or("").trim().to_lowercase();
+                let format = OUTPUT_FORMAT_DEFS.iter().find(|(_, m, _)| *m == media_type)?.2;
+                let q = params
+                    .filter_map(|p| p.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f64>().ok())
+                    .unwrap_or(1.0);
+                Some((q, format))
+            })
+            .filter(|(q, _)| *q > 0.0)
+            .collect();
+        // Stable, so the listed order breaks ties
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+        candidates.first().map(|c| c.1)
+    }
+
+    pub fn media_type(&self) -> &'static str {
+        OUTPUT_FORMAT_DEFS
+            .iter()
+            .find(|(_, _, f)| f == self)
+            .map_or("text/plain", |e| e.1)
+    }
+
+    pub fn formatter(&self) -> Box<dyn ResultFormatter> {
+        match self {
+            OutputFormat::Text => Box::new(TextFormatter),
+            OutputFormat::Csv => Box::new(CsvFormatter),
+            OutputFormat::Json => Box::new(JsonFormatter),
+            OutputFormat::Ndjson => Box::new(NdjsonFormatter),
+            OutputFormat::Markdown => Box::new(MarkdownFormatter),
+            OutputFormat::Html => Box::new(HtmlFormatter),
+        }
+    }
+
+    pub fn render(&self, table: &ResultTable) -> String {
+        self.formatter().format(table)
+    }
+}
+
+pub fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::Null => MISSING_VALUE.to_owned(),
+        Value::Integer(v) => v.to_string(),
+        Value::Real(v) => v.to_string(),
+        Value::Text(v) => v.to_owned(),
+        Value::Blob(v) => String::from_utf8_lossy(v).into_owned(),
+    }
+}
+
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_owned(),
+        Value::Integer(v) => v.to_string(),
+        // JSON has no representation for NaN/inf
+        Value::Real(v) if !v.is_finite() => "null".to_owned(),
+        Value::Real(v) => v.to_string(),
+        Value::Text(_) | Value::Blob(_) => json_escape(&value_to_string(value)),
+    }
+}
+
+fn value_type(value: &Value) -> &'static str {
+    // SQLite storage class of the value, as reported in JSON column metadata
+    match value {
+        Value::Null => "null",
+        Value::Integer(_) => "integer",
+        Value::Real(_) => "real",
+        Value::Text(_) => "text",
+        Value::Blob(_) => "blob",
+    }
+}
+
+fn column_type(table: &ResultTable, column: usize) -> &'static str {
+    // Missing values are ignored; integer columns with some real values are real, any other mix is text.
+    // "null" if the column has no values at all
+    table
+        .rows
+        .iter()
+        .map(|r| value_type(&r[column]))
+        .filter(|t| *t != "null")
+        .fold("null", |acc, t| match (acc, t) {
+            ("null", t) => t,
+            (acc, t) if acc == t => acc,
+            ("integer", "real") | ("real", "integer") => "real",
+            _ => "text",
+        })
+}
+
+// Arrays and objects nested deeper than this are printed by pretty_json on one line, so that e.g. each row of a
+// JsonFormatter response gets a line of its own
+const JSON_INLINE_DEPTH: usize = 2;
+
+pub fn pretty_json(json: &str) -> String {
+    // Re-indents compact JSON (as written by the formatters here) with two spaces per level; empty arrays and objects
+    // stay on one line
+    let mut pretty = String::with_capacity(json.len() * 2);
+    let (mut depth, mut in_string, mut escaped) = (0usize, false, false);
+    let mut chars = json.chars().peekable();
+    let newline = |pretty: &mut String, depth: usize| {
+        pretty.push('\n');
+        pretty.push_str(&"  ".repeat(depth));
+    };
+    while let Some(c) = chars.next() {
+        if in_string {
+            pretty.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => (),
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                pretty.push(c);
+            }
+            '{' | '[' => {
+                pretty.push(c);
+                if chars.peek().is_some_and(|n| *n == '}' || *n == ']') {
+                    pretty.push(chars.next().unwrap());
+                } else {
+                    depth += 1;
+                    if depth <= JSON_INLINE_DEPTH {
+                        newline(&mut pretty, depth);
+                    }
+                }
+            }
+            '}' | ']' => {
+                if depth <= JSON_INLINE_DEPTH {
+                    newline(&mut pretty, depth.saturating_sub(1));
+                }
+                depth = depth.saturating_sub(1);
+                pretty.push(c);
+            }
+            ',' => {
+                pretty.push(c);
+                match depth <= JSON_INLINE_DEPTH {
+                    true => newline(&mut pretty, depth),
+                    false => pretty.push(' '),
+                }
+            }
+            ':' => pretty.push_str(": "),
+            c if c.is_whitespace() => (),
+            c => pretty.push(c),
+        }
+    }
+    pretty
+}
+
+fn json_object(column_names: &[String], row: &[Value]) -> String {
+    let members: Vec<String> = column_names
+        .iter()
+        .zip(row)
+        .map(|(c, v)| format!("{}:{}", json_escape(c), json_value(v)))
+        .collect();
+    format!("{{{}}}", members.join(","))
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// Separates the columns of each TextFormatter line
+pub const TEXT_SEPARATOR: &str = "\t|\t";
+
+fn escape_text(s: String) -> String {
+    // Tabs and newlines inside values would break the row/column layout of TextFormatter, so escape them
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+pub fn unescape_text(s: &str) -> String {
+    // Inverse of escape_text, for a single cell of TextFormatter output
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+// Tab-separated text, as displayed by the client CLI
+pub struct TextFormatter;
+impl ResultFormatter for TextFormatter {
+    fn format(&self, table: &ResultTable) -> String {
+        let column_names = table
+            .column_names
+            .iter()
+            .map(|e| escape_text(e.to_owned()))
+            .collect::<Vec<String>>()
+            .join(TEXT_SEPARATOR);
+        let values = table
+            .rows
+            .iter()
+            .map(|r| {
+                r.iter()
+                    .map(|v| escape_text(value_to_string(v)))
+                    .collect::<Vec<String>>()
+                    .join(TEXT_SEPARATOR)
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        format!("{}\n{}", column_names, values)
+    }
+}
+
+// RFC 4180 CSV, except that records are separated by LF rather than CRLF. Missing values are empty fields
+pub struct CsvFormatter;
+impl ResultFormatter for CsvFormatter {
+    fn format(&self, table: &ResultTable) -> String {
+        let field = |s: String| {
+            if s.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", s.replace('"', "\"\""))
+            } else {
+                s
+            }
+        };
+        let mut lines: Vec<String> = vec![table
+            .column_names
+            .iter()
+            .map(|c| field(c.to_owned()))
+            .collect::<Vec<String>>()
+            .join(",")];
+        lines.extend(table.rows.iter().map(|r| {
+            r.iter()
+                .map(|v| match v {
+                    Value::Null => "".to_owned(),
+                    v => field(value_to_string(v)),
+                })
+                .collect::<Vec<String>>()
+                .join(",")
+        }));
+        lines.join("\n")
+    }
+}
+
+// Object with the columns' names and types, the rows as objects keyed by column name, and the row count:
+// {"columns":[{"name":"id","type":"integer"},...],"rows":[{"id":1,...},...],"row_count":1}
+pub struct JsonFormatter;
+impl ResultFormatter for JsonFormatter {
+    fn format(&self, table: &ResultTable) -> String {
+        let columns: Vec<String> = table
+            .column_names
+            .iter()
+            .enumerate()
+            .map(|(i, c)| format!("{{\"name\":{},\"type\":\"{}\"}}", json_escape(c), column_type(table, i)))
+            .collect();
+        let objects: Vec<String> = table.rows.iter().map(|r| json_object(&table.column_names, r)).collect();
+        format!(
+            "{{\"columns\":[{}],\"rows\":[{}],\"row_count\":{}}}",
+            columns.join(","),
+            objects.join(","),
+            table.rows.len()
+        )
+    }
+}
+
+// One row object per line
+pub struct NdjsonFormatter;
+impl ResultFormatter for NdjsonFormatter {
+    fn format(&self, table: &ResultTable) -> String {
+        table
+            .rows
+            .iter()
+            .map(|r| json_object(&table.column_names, r))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+pub struct MarkdownFormatter;
+impl ResultFormatter for MarkdownFormatter {
+    fn format(&self, table: &ResultTable) -> String {
+        let cell = |s: String| {
+            s.replace('\\', "\\\\")
+                .replace('|', "\\|")
+                .replace("\r\n", "<br>")
+                .replace(['\n', '\r'], "<br>")
+        };
+        let row = |cells: Vec<String>| format!("| {} |", cells.join(" | "));
+        let mut lines = vec![
+            row(table.column_names.iter().map(|c| cell(c.to_owned())).collect()),
+            row(table.column_names.iter().map(|_| "---".to_owned()).collect()),
+        ];
+        lines.extend(
+            table
+                .rows
+                .iter()
+                .map(|r| row(r.iter().map(|v| cell(value_to_string(v))).collect())),
+        );
+        lines.join("\n")
+    }
+}
+
+pub struct HtmlFormatter;
+impl ResultFormatter for HtmlFormatter {
+    fn format(&self, table: &ResultTable) -> String {
+        let header: String = table
+            .column_names
+            .iter()
+            .map(|c| format!("<th>{}</th>", html_escape(c)))
+            .collect();
+        let body: String = table
+            .rows
+            .iter()
+            .map(|r| {
+                let cells: String = r
+                    .iter()
+                    .map(|v| format!("<td>{}</td>", html_escape(&value_to_string(v))))
+                    .collect();
+                format!("<tr>{}</tr>\n", cells)
+            })
+            .collect();
+        format!(
+            "<table>\n<thead>\n<tr>{}</tr>\n</thead>\n<tbody>\n{}</tbody>\n</table>",
+            header, body
+        )
+    }
+}
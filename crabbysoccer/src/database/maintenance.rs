@@ -0,0 +1,69 @@
+use super::{DbError, DB};
+use rusqlite::{self, backup::Backup, Connection};
+use std::time::Duration;
+
+// Online backup is copied in steps of BACKUP_PAGES_PER_STEP pages, sleeping BACKUP_STEP_PAUSE_MS between
+// steps so that connection threads can keep reading (and writing) while the backup runs
+const BACKUP_PAGES_PER_STEP: std::os::raw::c_int = 128;
+const BACKUP_STEP_PAUSE_MS: u64 = 10;
+
+impl DB {
+    fn file_size(&self) -> Option<u64> {
+        self.connection
+            .path()
+            .and_then(|p| std::fs::metadata(p).ok())
+            .map(|m| m.len())
+    }
+
+    pub fn backup(&self, path: &str) -> Result<String, DbError> {
+        // Refuse to overwrite anything already at path, since the backup would replace its contents
+        if std::path::Path::new(path).exists() {
+            return Err(DbError::InvalidArgument(format!(
+                "Backup destination already exists: {}",
+                path
+            )));
+        }
+        let mut destination = Connection::open(path)?;
+        let backup = Backup::new(&self.connection, &mut destination)?;
+        backup.run_to_completion(BACKUP_PAGES_PER_STEP, Duration::from_millis(BACKUP_STEP_PAUSE_MS), None)?;
+        let pages = backup.progress().pagecount;
+        Ok(format!("Backed up {} page(s) to {}", pages, path))
+    }
+
+    pub fn vacuum(&self) -> Result<String, DbError> {
+        let before = self.file_size();
+        self.connection.execute_batch("VACUUM;")?;
+        Ok(match (before, self.file_size()) {
+            (Some(before), Some(after)) => format!("Vacuum complete: {} bytes -> {} bytes", before, after),
+            _ => "Vacuum complete".to_owned(),
+        })
+    }
+
+    pub fn analyze(&self) -> Result<String, DbError> {
+        self.connection.execute_batch("ANALYZE;")?;
+        // ANALYZE only records statistics for tables/indexes that have content
+        let analyzed: i64 = self
+            .connection
+            .query_row("SELECT COUNT(DISTINCT tbl) FROM sqlite_stat1;", [], |row| row.get(0))?;
+        Ok(format!(
+            "Analyze complete: statistics gathered for {} table(s)",
+            analyzed
+        ))
+    }
+
+    pub fn integrity_check(&self) -> Result<String, DbError> {
+        let mut statement = self.connection.prepare("PRAGMA integrity_check;")?;
+        let problems = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+        Ok(if problems.len() == 1 && problems[0] == "ok" {
+            "Integrity check passed: ok".to_owned()
+        } else {
+            format!(
+                "Integrity check found {} problem(s):\n{}",
+                problems.len(),
+                problems.join("\n")
+            )
+        })
+    }
+}
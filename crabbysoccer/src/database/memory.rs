@@ -0,0 +1,618 @@
+use super::{
+    csv_attributes,
+    format::{self, ResultTable},
+    hide_irrelevant_statistics, import_csv, parse_cell, saved_query, select_statistics, sub_position, validate,
+    watchlist, DbError, DuplicatePolicy, ImportError, PlayerStatistics, PlayerStore, TableName, WatchlistEntry,
+    POSITION_SEPARATOR, STATISTICS_COLUMNS,
+};
+use rusqlite::types::Value;
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc, PoisonError, RwLock,
+};
+
+// Columns of the player table, in table order
+const PLAYER_COLUMNS: [&str; 6] = ["id", "name", "jersey_number", "club_name", "nationality", "age"];
+
+// Player columns stored as text, mirroring the VARCHAR columns of the player table
+const TEXT_PLAYER_COLUMNS: [&str; 3] = ["name", "club_name", "nationality"];
+
+struct PlayerRecord {
+    // Aligned with PLAYER_COLUMNS
+    player: Vec<Value>,
+    // Broad positions, in csv order
+    positions: Vec<String>,
+    sub_positions: Vec<String>,
+    // Aligned with STATISTICS_COLUMNS
+    statistics: Vec<Value>,
+}
+impl PlayerRecord {
+    fn id(&self) -> i64 {
+        match self.player[0] {
+            Value::Integer(id) => id,
+            _ => 0,
+        }
+    }
+
+    fn text(&self, column: &str) -> Option<String> {
+        match PLAYER_COLUMNS
+            .iter()
+            .position(|c| *c == column)
+            .map(|i| &self.player[i])
+        {
+            Some(Value::Text(v)) => Some(v.clone()),
+            _ => None,
+        }
+    }
+
+    fn statistic(&self, statistic: &str) -> Value {
+        STATISTICS_COLUMNS
+            .iter()
+            .position(|c| *c == statistic)
+            .map_or(Value::Null, |i| self.statistics[i].clone())
+    }
+
+    fn plays_position(&self, position: Option<&str>) -> bool {
+        position.is_none_or(|p| sub_position::plays_position(&self.positions, &self.sub_positions, p))
+    }
+}
+
+struct Watchlist {
+    user: String,
+    name: String,
+    // In the order they were added
+    player_ids: Vec<i64>,
+}
+
+struct PlayerNote {
+    user: String,
+    player_id: i64,
+    note: Option<String>,
+    tags: Option<String>,
+}
+
+fn numeric(value: Value) -> Value {
+    // Whole reals are stored as integers, as SQLite's INTEGER and DECIMAL column affinities do
+    match value {
+        Value::Real(v) if v.fract() == 0.0 && v.abs() < i64::MAX as f64 => Value::Integer(v as i64),
+        v => v,
+    }
+}
+
+fn as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(v) => Some(*v as f64),
+        Value::Real(v) => Some(*v),
+        _ => None,
+    }
+}
+
+// Player data held in memory, loaded from soccer.csv without going through soccer.db
+// Clones share the same data, so writes made through one connection are seen by every other
+#[derive(Clone)]
+pub struct MemoryStore {
+    players: Arc<RwLock<Vec<PlayerRecord>>>,
+    watchlists: Arc<RwLock<Vec<Watchlist>>>,
+    notes: Arc<RwLock<Vec<PlayerNote>>>,
+    // Format: (name, query)
+    saved_queries: Arc<RwLock<Vec<(String, String)>>>,
+    changes: Arc<AtomicI64>,
+}
+impl MemoryStore {
+    pub fn load(policy: DuplicatePolicy) -> Result<Self, ImportError> {
+        let (header, data) = import_csv(policy)?;
+        Self::from_rows(&header, &data)
+    }
+
+    fn from_rows(header: &[String], data: &[Vec<String>]) -> Result<Self, ImportError> {
+        // Mirrors csv_to_sqlite: players are numbered from 1 in csv order
+        let attributes = csv_attributes(header)?;
+        let mut players: Vec<PlayerRecord> = Vec::with_capacity(data.len());
+        for (i, row) in data.iter().enumerate() {
+            let mut record = PlayerRecord {
+                player: vec![Value::Null; PLAYER_COLUMNS.len()],
+                positions: vec![],
+                sub_positions: vec![],
+                statistics: vec![Value::Null; STATISTICS_COLUMNS.len()],
+            };
+            record.player[0] = Value::Integer(i as i64 + 1);
+            for ((table_name, attribute), cell) in attributes.iter().zip(row) {
+                match table_name {
+                    TableName::Player => {
+                        let idx = PLAYER_COLUMNS.iter().position(|c| c == attribute).unwrap_or(0);
+                        record.player[idx] = match parse_cell(cell)? {
+                            Value::Null => Value::Null,
+                            _ if TEXT_PLAYER_COLUMNS.contains(attribute) => Value::Text(cell.trim().to_owned()),
+                            v => numeric(v),
+                        };
+                    }
+                    TableName::Statistics => {
+                        if let Some(idx) = STATISTICS_COLUMNS.iter().position(|c| c == attribute) {
+                            record.statistics[idx] = numeric(parse_cell(cell)?);
+                        }
+                    }
+                    TableName::Position => {
+                        for p in cell.split(POSITION_SEPARATOR).map(str::trim) {
+                            let position = sub_position::broad_position_of(p).unwrap_or(p);
+                            if !sub_position::SUB_POSITIONS.iter().any(|(_, b)| *b == position) {
+                                return Err(ImportError::MalformedCsv(format!(
+                                    "line {}: invalid position: {}",
+                                    i + 2,
+                                    p
+                                )));
+                            }
+                            if !record.positions.iter().any(|e| e == position) {
+                                record.positions.push(position.to_owned());
+                            }
+                            if position != p && !record.sub_positions.iter().any(|e| e == p) {
+                                record.sub_positions.push(p.to_owned());
+                            }
+                        }
+                    }
+                }
+            }
+            if record.player[1] == Value::Null {
+                return Err(ImportError::MalformedCsv(format!("line {}: missing Name", i + 2)));
+            }
+            players.push(record);
+        }
+        println!("Loaded {} player(s) into memory", players.len());
+        Ok(Self {
+            players: Arc::new(RwLock::new(players)),
+            watchlists: Arc::new(RwLock::new(vec![])),
+            notes: Arc::new(RwLock::new(vec![])),
+            saved_queries: Arc::new(RwLock::new(vec![])),
+            changes: Arc::new(AtomicI64::new(0)),
+        })
+    }
+
+    fn ensure_players_exist(&self, player_ids: &[i64]) -> Result<(), DbError> {
+        let players = self.players.read().unwrap_or_else(PoisonError::into_inner);
+        match player_ids.iter().find(|id| !players.iter().any(|r| r.id() == **id)) {
+            Some(id) => Err(DbError::NotFound(format!("No such player: {}", id))),
+            None => Ok(()),
+        }
+    }
+
+    fn with_watchlist<T>(&self, user: &str, list: &str, f: impl FnOnce(&mut Watchlist) -> T) -> Result<T, DbError> {
+        let (user, list) = (
+            watchlist::validate_name("user", user)?,
+            watchlist::validate_name("list", list)?,
+        );
+        let mut watchlists = self.watchlists.write().unwrap_or_else(PoisonError::into_inner);
+        watchlists
+            .iter_mut()
+            .find(|w| w.user == user && w.name == list)
+            .map(f)
+            .ok_or(DbError::NotFound(format!("No such watchlist: {}", list)))
+    }
+}
+
+impl PlayerStore for MemoryStore {
+    fn get_all_players(&self, name: Option<String>, position: Option<String>) -> Result<ResultTable, DbError> {
+        // name matches case-insensitively anywhere in the player's name, like get_all_players' LIKE
+        let name = name.map(|n| n.to_lowercase());
+        let players = self.players.read().unwrap_or_else(PoisonError::into_inner);
+        let rows: Vec<Vec<Value>> = players
+            .iter()
+            .filter(|r| match &name {
+                Some(name) => r.text("name").is_some_and(|n| n.to_lowercase().contains(name)),
+                None => true,
+            })
+            .filter(|r| r.plays_position(position.as_deref()))
+            .map(|r| r.player.clone())
+            .collect();
+        Ok(ResultTable::new(&PLAYER_COLUMNS, rows))
+    }
+
+    fn get_player(
+        &self,
+        player_id: Option<String>,
+        statistics: Option<Vec<String>>,
+        position: Option<String>,
+    ) -> Result<ResultTable, DbError> {
+        let (statistics, explicit) = select_statistics(statistics)?;
+        let player_id: Option<i64> = player_id
+            .map(|id| {
+                id.parse()
+                    .map_err(|_| DbError::InvalidArgument(format!("Invalid player_id: {}", id)))
+            })
+            .transpose()?;
+        let join = |values: &[String]| match values.is_empty() {
+            true => Value::Null,
+            false => Value::Text(values.join(&POSITION_SEPARATOR.to_string())),
+        };
+        let players = self.players.read().unwrap_or_else(PoisonError::into_inner);
+        // Players without a position are left out, as they are by get_player's join
+        let rows: Vec<Vec<Value>> = players
+            .iter()
+            .filter(|r| !r.positions.is_empty())
+            .filter(|r| player_id.is_none_or(|id| r.id() == id))
+            .filter(|r| r.plays_position(position.as_deref()))
+            .map(|r| {
+                let mut row = vec![
+                    r.player[0].clone(),
+                    r.player[1].clone(),
+                    join(&r.positions),
+                    join(&r.sub_positions),
+                ];
+                row.extend(statistics.iter().map(|s| r.statistic(s)));
+                row
+            })
+            .collect();
+        let mut column_names: Vec<String> = ["id", "name", "position", "sub_position"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        column_names.extend(statistics);
+        let mut table = ResultTable::new(&column_names, rows);
+        hide_irrelevant_statistics(&mut table, &explicit);
+        Ok(table)
+    }
+
+    fn player_statistics(
+        &self,
+        club: Option<&str>,
+        position: Option<&str>,
+        statistics: &[&'static str],
+    ) -> Result<Vec<PlayerStatistics>, DbError> {
+        let players = self.players.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(players
+            .iter()
+            .filter(|r| !r.positions.is_empty())
+            .filter(|r| club.is_none_or(|c| r.text("club_name").as_deref() == Some(c)))
+            .filter(|r| r.plays_position(position))
+            .map(|r| PlayerStatistics {
+                id: r.id(),
+                name: r.text("name").unwrap_or_default(),
+                club_name: r.text("club_name"),
+                nationality: r.text("nationality"),
+                age: match r.player[5] {
+                    Value::Integer(age) => Some(age),
+                    _ => None,
+                },
+                positions: r.positions.clone(),
+                values: statistics.iter().map(|s| as_f64(&r.statistic(s))).collect(),
+            })
+            .collect())
+    }
+
+    fn set_sub_positions(&self, player_id: String, sub_positions: Vec<String>) -> Result<ResultTable, DbError> {
+        // Replaces the player's sub-positions, and the broad positions they imply (see rebuild_positions)
+        let id: i64 = player_id
+            .parse()
+            .map_err(|_| DbError::InvalidArgument(format!("Invalid player_id: {}", player_id)))?;
+        for sub_position in &sub_positions {
+            if sub_position::broad_position_of(sub_position).is_none() {
+                return Err(DbError::InvalidArgument(format!(
+                    "No such sub-position: {}",
+                    sub_position
+                )));
+            }
+        }
+        {
+            let mut players = self.players.write().unwrap_or_else(PoisonError::into_inner);
+            let record = players
+                .iter_mut()
+                .find(|r| r.id() == id)
+                .ok_or(DbError::NotFound(format!("No such player: {}", id)))?;
+            record.positions =
+                sub_position::rebuild_positions(&record.positions, &record.sub_positions, &sub_positions);
+            record.sub_positions.clear();
+            for sub_position in &sub_positions {
+                if !record.sub_positions.contains(sub_position) {
+                    record.sub_positions.push(sub_position.clone());
+                }
+            }
+        }
+        self.changes.fetch_add(1, Ordering::SeqCst);
+        self.get_player(Some(id.to_string()), None, None)
+    }
+
+    fn validate(&self, rules: Option<Vec<String>>) -> Result<ResultTable, DbError> {
+        // As DB::validate, with each rule's condition checked row by row, in rule then player id order
+        let rules = validate::select_rules(rules)?;
+        let players = self.players.read().unwrap_or_else(PoisonError::into_inner);
+        let mut by_id: Vec<&PlayerRecord> = players.iter().collect();
+        by_id.sort_by_key(|r| r.id());
+        let mut rows: Vec<Vec<Value>> = vec![];
+        for rule in rules {
+            for record in &by_id {
+                if (rule.offends)(&|s| as_f64(&record.statistic(s)), &record.positions) {
+                    let values: Vec<Value> = rule.columns.iter().map(|c| record.statistic(c)).collect();
+                    rows.push(validate::offending_row(
+                        rule,
+                        record.player[0].clone(),
+                        record.player[1].clone(),
+                        &values,
+                    ));
+                }
+            }
+        }
+        Ok(ResultTable::new(&validate::VALIDATION_COLUMNS, rows))
+    }
+
+    fn create_watchlist(&self, user: String, list: String) -> Result<ResultTable, DbError> {
+        let (user, list) = (
+            watchlist::validate_name("user", &user)?,
+            watchlist::validate_name("list", &list)?,
+        );
+        {
+            let mut watchlists = self.watchlists.write().unwrap_or_else(PoisonError::into_inner);
+            if watchlists.iter().any(|w| w.user == user && w.name == list) {
+                return Err(DbError::InvalidArgument(format!("Watchlist already exists: {}", list)));
+            }
+            watchlists.push(Watchlist {
+                user: user.to_owned(),
+                name: list.to_owned(),
+                player_ids: vec![],
+            });
+        }
+        self.changes.fetch_add(1, Ordering::SeqCst);
+        self.get_watchlists(user.to_owned())
+    }
+
+    fn get_watchlists(&self, user: String) -> Result<ResultTable, DbError> {
+        let user = watchlist::validate_name("user", &user)?;
+        let watchlists = self.watchlists.read().unwrap_or_else(PoisonError::into_inner);
+        let mut rows: Vec<Vec<Value>> = watchlists
+            .iter()
+            .filter(|w| w.user == user)
+            .map(|w| vec![Value::Text(w.name.clone()), Value::Integer(w.player_ids.len() as i64)])
+            .collect();
+        rows.sort_by(|a, b| format::value_to_string(&a[0]).cmp(&format::value_to_string(&b[0])));
+        Ok(ResultTable::new(&["name", "players"], rows))
+    }
+
+    fn add_to_watchlist(&self, user: String, list: String, player_ids: Vec<String>) -> Result<ResultTable, DbError> {
+        // Players already on the list are left where they are
+        let player_ids = watchlist::parse_player_ids(&player_ids)?;
+        self.ensure_players_exist(&player_ids)?;
+        self.with_watchlist(&user, &list, |w| {
+            for player_id in player_ids {
+                if !w.player_ids.contains(&player_id) {
+                    w.player_ids.push(player_id);
+                }
+            }
+        })?;
+        self.changes.fetch_add(1, Ordering::SeqCst);
+        self.get_watchlist(user, list, None)
+    }
+
+    fn remove_from_watchlist(
+        &self,
+        user: String,
+        list: String,
+        player_ids: Vec<String>,
+    ) -> Result<ResultTable, DbError> {
+        let player_ids = watchlist::parse_player_ids(&player_ids)?;
+        self.with_watchlist(&user, &list, |w| {
+            // Nothing is removed unless every player is on the list, as with the sqlite transaction
+            match player_ids.iter().find(|id| !w.player_ids.contains(id)) {
+                Some(id) => Err(DbError::NotFound(format!(
+                    "Player {} is not on watchlist {}",
+                    id, w.name
+                ))),
+                None => {
+                    w.player_ids.retain(|id| !player_ids.contains(id));
+                    Ok(())
+                }
+            }
+        })??;
+        self.changes.fetch_add(1, Ordering::SeqCst);
+        self.get_watchlist(user, list, None)
+    }
+
+    fn set_note(
+        &self,
+        user: String,
+        player_id: String,
+        note: Option<String>,
+        tags: Vec<String>,
+    ) -> Result<ResultTable, DbError> {
+        // Replaces the user's note and tags on the player; a blank note with no tags removes it
+        let user = watchlist::validate_name("user", &user)?;
+        let player_id = watchlist::parse_player_ids(&[player_id])?[0];
+        self.ensure_players_exist(&[player_id])?;
+        let note = note.map(|n| n.trim().to_owned()).filter(|n| !n.is_empty());
+        let tags = watchlist::join_tags(&tags);
+        {
+            let mut notes = self.notes.write().unwrap_or_else(PoisonError::into_inner);
+            notes.retain(|n| n.user != user || n.player_id != player_id);
+            if note.is_some() || tags.is_some() {
+                notes.push(PlayerNote {
+                    user: user.to_owned(),
+                    player_id,
+                    note: note.clone(),
+                    tags: tags.clone(),
+                });
+            }
+        }
+        self.changes.fetch_add(1, Ordering::SeqCst);
+        Ok(watchlist::note_table(player_id, note, tags))
+    }
+
+    fn watchlist_entries(&self, user: &str, list: &str) -> Result<Vec<WatchlistEntry>, DbError> {
+        let player_ids = self.with_watchlist(user, list, |w| w.player_ids.clone())?;
+        let user = watchlist::validate_name("user", user)?;
+        let notes = self.notes.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(player_ids
+            .into_iter()
+            .map(|player_id| {
+                let note = notes.iter().find(|n| n.user == user && n.player_id == player_id);
+                WatchlistEntry {
+                    player_id,
+                    note: note.and_then(|n| n.note.clone()),
+                    tags: note.and_then(|n| n.tags.clone()),
+                }
+            })
+            .collect())
+    }
+
+    fn save_query(&self, name: String, query: String) -> Result<ResultTable, DbError> {
+        // Saving under an existing name replaces that query
+        let name = saved_query::validate_query_name(&name)?;
+        {
+            let mut saved_queries = self.saved_queries.write().unwrap_or_else(PoisonError::into_inner);
+            saved_queries.retain(|(n, _)| n != name);
+            saved_queries.push((name.to_owned(), query.trim().to_owned()));
+        }
+        self.changes.fetch_add(1, Ordering::SeqCst);
+        Ok(saved_query::saved_query_table(name, query.trim()))
+    }
+
+    fn saved_query(&self, name: &str) -> Result<String, DbError> {
+        let name = saved_query::validate_query_name(name)?;
+        let saved_queries = self.saved_queries.read().unwrap_or_else(PoisonError::into_inner);
+        saved_queries
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, query)| query.clone())
+            .ok_or(DbError::NotFound(format!("No such saved query: {}", name)))
+    }
+
+    fn get_saved_queries(&self) -> Result<ResultTable, DbError> {
+        let mut saved_queries: Vec<(String, String)> = self
+            .saved_queries
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        saved_queries.sort();
+        Ok(ResultTable::new(
+            &["name", "query"],
+            saved_queries
+                .into_iter()
+                .map(|(name, query)| vec![Value::Text(name), Value::Text(query)])
+                .collect(),
+        ))
+    }
+
+    fn total_changes(&self) -> Result<i64, DbError> {
+        Ok(self.changes.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> MemoryStore {
+        let header = [
+            "Name",
+            "Club",
+            "Position",
+            "Appearances",
+            "Wins",
+            "Losses",
+            "Goals",
+            "Saves",
+        ];
+        let data = [
+            ["Alisson", "Liverpool", "Goalkeeper", "10", "5", "3", "0", "40"],
+            ["Virgil", "Liverpool", "CB", "10", "6", "8", "2", ""],
+            ["Mo", "Liverpool", "Forward", "10", "7", "2", "12", ""],
+            ["Andy", "Liverpool", "Defender", "8", "4", "3", "1", "5"],
+        ];
+        let strings = |cells: &[&str]| cells.iter().map(|c| c.to_string()).collect::<Vec<String>>();
+        let data: Vec<Vec<String>> = data.iter().map(|row| strings(row)).collect();
+        MemoryStore::from_rows(&strings(&header), &data).unwrap()
+    }
+
+    fn ids(store: &MemoryStore, position: &str) -> Vec<i64> {
+        let players = store.player_statistics(None, Some(position), &[]).unwrap();
+        players.iter().map(|p| p.id).collect()
+    }
+
+    fn positions(store: &MemoryStore, id: i64) -> Vec<String> {
+        let players = store.player_statistics(None, None, &[]).unwrap();
+        players.into_iter().find(|p| p.id == id).unwrap().positions
+    }
+
+    #[test]
+    fn get_player_returns_requested_statistics() {
+        let table = store()
+            .get_player(Some("3".to_owned()), Some(vec!["goals".to_owned()]), None)
+            .unwrap();
+        assert_eq!(table.column_names, ["id", "name", "position", "sub_position", "goals"]);
+        assert_eq!(
+            table.rows,
+            [[
+                Value::Integer(3),
+                Value::Text("Mo".to_owned()),
+                Value::Text("Forward".to_owned()),
+                Value::Null,
+                Value::Integer(12),
+            ]]
+        );
+    }
+
+    #[test]
+    fn get_player_hides_irrelevant_statistics() {
+        let store = store();
+        let forward = store
+            .get_player(Some("3".to_owned()), Some(vec!["all".to_owned()]), None)
+            .unwrap();
+        assert!(!forward.column_names.iter().any(|c| c == "saves"));
+        let goalkeeper = store
+            .get_player(Some("1".to_owned()), Some(vec!["all".to_owned()]), None)
+            .unwrap();
+        assert!(goalkeeper.column_names.iter().any(|c| c == "saves"));
+    }
+
+    #[test]
+    fn player_statistics_filters_by_position() {
+        let store = store();
+        assert_eq!(ids(&store, "Defender"), [2, 4]);
+        assert_eq!(ids(&store, "CB"), [2]);
+        assert_eq!(ids(&store, "Goalkeeper"), [1]);
+        assert_eq!(ids(&store, "ST"), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn set_sub_positions_rebuilds_positions() {
+        let store = store();
+        let set = |id: i64, sub_positions: &[&str]| {
+            let sub_positions = sub_positions.iter().map(|s| s.to_string()).collect();
+            store.set_sub_positions(id.to_string(), sub_positions)
+        };
+        // Defender was only implied by CB, so is replaced
+        set(2, &["ST"]).unwrap();
+        assert_eq!(positions(&store, 2), ["Forward"]);
+        assert_eq!(ids(&store, "CB"), Vec::<i64>::new());
+        assert_eq!(ids(&store, "ST"), [2]);
+        // Defender was given on its own, so is kept
+        set(4, &["DM"]).unwrap();
+        assert_eq!(positions(&store, 4), ["Defender", "Midfielder"]);
+        // Clearing every sub-position keeps the broad positions
+        set(2, &[]).unwrap();
+        assert_eq!(positions(&store, 2), ["Forward"]);
+        assert!(matches!(set(2, &["XX"]), Err(DbError::InvalidArgument(_))));
+        assert!(matches!(set(99, &["ST"]), Err(DbError::NotFound(_))));
+    }
+
+    #[test]
+    fn validate_reports_offending_rows() {
+        let store = store();
+        let report = store.validate(None).unwrap();
+        let offending: Vec<(&Value, &Value)> = report.rows.iter().map(|r| (&r[0], &r[1])).collect();
+        assert_eq!(
+            offending,
+            [
+                (
+                    &Value::Text("results-within-appearances".to_owned()),
+                    &Value::Integer(2)
+                ),
+                (
+                    &Value::Text("goalkeeper-stats-on-outfield-player".to_owned()),
+                    &Value::Integer(4)
+                ),
+            ]
+        );
+        let rules = Some(vec!["shots-on-target-within-shots".to_owned()]);
+        assert!(store.validate(rules).unwrap().rows.is_empty());
+        assert!(matches!(
+            store.validate(Some(vec!["no-such-rule".to_owned()])),
+            Err(DbError::InvalidArgument(_))
+        ));
+    }
+}
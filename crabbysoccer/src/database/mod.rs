@@ -1,7 +1,34 @@
-use rusqlite::{self, types::ValueRef, Connection, Row, Statement};
-use std::{collections::HashMap, fmt::Display, fs::read_to_string, path::Path};
+use rusqlite::{self, types::Value, Connection, Params, Row, Statement, ToSql};
+use std::{cell::RefCell, collections::HashMap, fmt::Display, fs::read_to_string, path::Path};
 use strum::EnumIter;
 
+mod analytics;
+mod best_xi;
+mod duplicates;
+mod error;
+mod fantasy;
+pub mod format;
+mod maintenance;
+mod memory;
+mod saved_query;
+mod store;
+mod sub_position;
+mod validate;
+mod watchlist;
+
+pub use analytics::{
+    aggregate_fn_names, correlation_method_names, correlation_view_names, group_by_names, DISTRIBUTION_BIN_MEASURE,
+    DISTRIBUTION_COLUMNS,
+};
+pub use duplicates::{DuplicatePolicy, DUPLICATES_ENV_VAR};
+pub use error::{DbError, ImportError};
+use format::ResultTable;
+pub use memory::MemoryStore;
+pub use store::{Backend, PlayerStatistics, PlayerStore, BACKEND_ENV_VAR};
+pub use sub_position::{position_names, sub_position_names};
+pub use validate::{summarize_validation, validation_rule_names};
+pub use watchlist::WatchlistEntry;
+
 trait TableNameTrait {
     fn as_str(&self) -> &str;
 }
@@ -26,15 +53,15 @@ impl Display for TableName {
         f.write_str(self.as_str())
     }
 }
-const CREATE_TABLE_QUERIES: [&str; 3] = [
+const CREATE_TABLE_QUERIES: [&str; 9] = [
     // player
     "CREATE TABLE player (
         id INTEGER PRIMARY KEY AUTOINCREMENT,
         name VARCHAR(128) NOT NULL,
-        jersey_number INTEGER NOT NULL,
+        jersey_number INTEGER,
         club_name VARCHAR(128),
-        nationality VARCHAR(64) NOT NULL,
-        age INTEGER NOT NULL);",
+        nationality VARCHAR(64),
+        age INTEGER);",
     // statistics
     "CREATE TABLE statistics (
         player_id INTEGER PRIMARY KEY,
@@ -42,41 +69,41 @@ const CREATE_TABLE_QUERIES: [&str; 3] = [
         wins INTEGER NOT NULL,
         losses INTEGER NOT NULL,
         goals INTEGER NOT NULL,
-        goals_per_match INTEGER NOT NULL,
-        headed_goals INTEGER NOT NULL,
-        goals_right_foot INTEGER NOT NULL,
-        goals_left_foot INTEGER NOT NULL,
-        goals_from_penalties INTEGER NOT NULL,
-        goals_from_freekicks INTEGER NOT NULL,
-        shots INTEGER NOT NULL,
-        shots_on_target INTEGER NOT NULL,
-        shooting_accuracy_pct DECIMAL(5,4) NOT NULL,
-        hit_woodwork INTEGER NOT NULL,
-        clean_sheets INTEGER NOT NULL,
-        goals_conceded INTEGER NOT NULL,
-        tackles INTEGER NOT NULL,
-        tackle_success_pct DECIMAL(5,4) NOT NULL,
-        shots_blocked INTEGER NOT NULL,
-        interceptions INTEGER NOT NULL,
-        clearances INTEGER NOT NULL,
-        headed_clearances INTEGER NOT NULL,
-        own_goals INTEGER NOT NULL,
+        goals_per_match INTEGER,
+        headed_goals INTEGER,
+        goals_right_foot INTEGER,
+        goals_left_foot INTEGER,
+        goals_from_penalties INTEGER,
+        goals_from_freekicks INTEGER,
+        shots INTEGER,
+        shots_on_target INTEGER,
+        shooting_accuracy_pct DECIMAL(5,4),
+        hit_woodwork INTEGER,
+        clean_sheets INTEGER,
+        goals_conceded INTEGER,
+        tackles INTEGER,
+        tackle_success_pct DECIMAL(5,4),
+        shots_blocked INTEGER,
+        interceptions INTEGER,
+        clearances INTEGER,
+        headed_clearances INTEGER,
+        own_goals INTEGER,
         assists INTEGER NOT NULL,
         passes INTEGER NOT NULL,
-        crosses INTEGER NOT NULL,
-        cross_accuracy_pct DECIMAL(5,4) NOT NULL,
+        crosses INTEGER,
+        cross_accuracy_pct DECIMAL(5,4),
         passes_per_match INTEGER NOT NULL,
-        saves INTEGER NOT NULL,
-        penalties_saved INTEGER NOT NULL,
-        punches INTEGER NOT NULL,
-        high_claims INTEGER NOT NULL,
-        catches INTEGER NOT NULL,
-        throw_outs INTEGER NOT NULL,
-        goal_kicks INTEGER NOT NULL,
+        saves INTEGER,
+        penalties_saved INTEGER,
+        punches INTEGER,
+        high_claims INTEGER,
+        catches INTEGER,
+        throw_outs INTEGER,
+        goal_kicks INTEGER,
         cards_yellow INTEGER NOT NULL,
         cards_red INTEGER NOT NULL,
         fouls INTEGER NOT NULL,
-        offsides INTEGER NOT NULL,
+        offsides INTEGER,
         FOREIGN KEY (player_id) REFERENCES player(id)
     );",
     // position
@@ -87,119 +114,389 @@ const CREATE_TABLE_QUERIES: [&str; 3] = [
         FOREIGN KEY (player_id) REFERENCES player(id),
         CONSTRAINT chk_position_name CHECK (name IN ('Forward', 'Midfielder', 'Goalkeeper', 'Defender'))
     );",
+    // sub_position
+    "CREATE TABLE sub_position (
+        name VARCHAR(10) PRIMARY KEY,
+        position_name VARCHAR(10) NOT NULL,
+        CONSTRAINT chk_sub_position_position_name CHECK (position_name IN ('Forward', 'Midfielder', 'Goalkeeper', 'Defender'))
+    );",
+    // player_sub_position
+    "CREATE TABLE player_sub_position (
+        player_id INTEGER,
+        sub_position_name VARCHAR(10),
+        PRIMARY KEY(player_id, sub_position_name),
+        FOREIGN KEY (player_id) REFERENCES player(id),
+        FOREIGN KEY (sub_position_name) REFERENCES sub_position(name)
+    );",
+    // watchlist
+    "CREATE TABLE watchlist (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        user_name VARCHAR(64) NOT NULL,
+        name VARCHAR(64) NOT NULL,
+        UNIQUE(user_name, name)
+    );",
+    // watchlist_player
+    "CREATE TABLE watchlist_player (
+        watchlist_id INTEGER,
+        player_id INTEGER,
+        PRIMARY KEY(watchlist_id, player_id),
+        FOREIGN KEY (watchlist_id) REFERENCES watchlist(id),
+        FOREIGN KEY (player_id) REFERENCES player(id)
+    );",
+    // player_note
+    "CREATE TABLE player_note (
+        user_name VARCHAR(64),
+        player_id INTEGER,
+        note TEXT,
+        tags TEXT,
+        PRIMARY KEY(user_name, player_id),
+        FOREIGN KEY (player_id) REFERENCES player(id)
+    );",
+    // saved_query
+    "CREATE TABLE saved_query (
+        name VARCHAR(64) PRIMARY KEY,
+        query TEXT NOT NULL
+    );",
+];
+
+// Column names of the statistics table (excluding player_id), in table order
+pub const STATISTICS_COLUMNS: [&str; 39] = [
+    "appearances",
+    "wins",
+    "losses",
+    "goals",
+    "goals_per_match",
+    "headed_goals",
+    "goals_right_foot",
+    "goals_left_foot",
+    "goals_from_penalties",
+    "goals_from_freekicks",
+    "shots",
+    "shots_on_target",
+    "shooting_accuracy_pct",
+    "hit_woodwork",
+    "clean_sheets",
+    "goals_conceded",
+    "tackles",
+    "tackle_success_pct",
+    "shots_blocked",
+    "interceptions",
+    "clearances",
+    "headed_clearances",
+    "own_goals",
+    "assists",
+    "passes",
+    "crosses",
+    "cross_accuracy_pct",
+    "passes_per_match",
+    "saves",
+    "penalties_saved",
+    "punches",
+    "high_claims",
+    "catches",
+    "throw_outs",
+    "goal_kicks",
+    "cards_yellow",
+    "cards_red",
+    "fouls",
+    "offsides",
+];
+
+// Statistics only ever recorded for goalkeepers
+const GOALKEEPER_ONLY_STATISTICS: [&str; 7] = [
+    "saves",
+    "penalties_saved",
+    "punches",
+    "high_claims",
+    "catches",
+    "throw_outs",
+    "goal_kicks",
+];
+
+// Statistics never recorded for goalkeepers
+const OUTFIELD_ONLY_STATISTICS: [&str; 19] = [
+    "goals_per_match",
+    "headed_goals",
+    "goals_right_foot",
+    "goals_left_foot",
+    "goals_from_penalties",
+    "goals_from_freekicks",
+    "shots",
+    "shots_on_target",
+    "shooting_accuracy_pct",
+    "hit_woodwork",
+    "tackles",
+    "tackle_success_pct",
+    "shots_blocked",
+    "interceptions",
+    "clearances",
+    "headed_clearances",
+    "crosses",
+    "cross_accuracy_pct",
+    "offsides",
 ];
 
-#[allow(dead_code)]
+// Statistics only recorded for goalkeepers and defenders
+const DEFENSIVE_ONLY_STATISTICS: [&str; 2] = ["clean_sheets", "goals_conceded"];
+
+fn statistic_relevant_to(statistic: &str, position: &str) -> bool {
+    match position {
+        "Goalkeeper" => !OUTFIELD_ONLY_STATISTICS.contains(&statistic),
+        "Defender" => !GOALKEEPER_ONLY_STATISTICS.contains(&statistic),
+        _ => !GOALKEEPER_ONLY_STATISTICS.contains(&statistic) && !DEFENSIVE_ONLY_STATISTICS.contains(&statistic),
+    }
+}
+
+// Separates positions in multi-position csv cells (e.g. "Defender/Midfielder") and in get-player output
+const POSITION_SEPARATOR: char = '/';
+
+// Displayed in place of NULL values (i.e., cells left blank in the source csv), so they are not mistaken for 0
+pub const MISSING_VALUE: &str = "N/A";
+
 const JOIN_ALL: &str =
     "player JOIN statistics ON player.id = statistics.player_id JOIN position ON player.id = position.player_id";
 
+const CREATE_INDEX_QUERIES: [&str; 4] = [
+    "CREATE INDEX idx_player_name ON player(name);",
+    "CREATE INDEX idx_player_club_name ON player(club_name);",
+    "CREATE INDEX idx_position_name ON position(name);",
+    "CREATE INDEX idx_player_sub_position_name ON player_sub_position(sub_position_name);",
+];
+
 pub struct DB {
     pub connection: Connection,
+    // SQL of every statement prepared through DB::prepare, see DB::explain_query_plan
+    prepared_sql: RefCell<Vec<String>>,
 }
 impl DB {
-    pub fn new() -> Self {
-        Self {
-            connection: Connection::open("soccer.db").unwrap(),
-        }
+    pub fn new() -> Result<Self, DbError> {
+        Ok(Self {
+            connection: DB::open_connection("soccer.db")?,
+            prepared_sql: RefCell::new(vec![]),
+        })
     }
-    fn parsed_rows_to_string<T: ToString, U: ToString>(column_names: &[T], values: &[Vec<U>]) -> String {
-        let column_names = column_names
-            .iter()
-            .map(|e| e.to_string())
-            .collect::<Vec<String>>()
-            .join("\t|\t");
-        let values = values
-            .iter()
-            .map(|e| e.iter().map(|e| e.to_string()).collect::<Vec<String>>().join("\t|\t"))
-            .collect::<Vec<String>>()
-            .join("\n");
-        format!("{}\n{}", column_names, values)
+
+    fn open_connection(path: &str) -> Result<Connection, rusqlite::Error> {
+        // SQLite leaves foreign key enforcement off unless enabled per connection
+        let connection = Connection::open(path)?;
+        connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+        Ok(connection)
     }
 
-    fn row_to_vec_string(n_columns: &usize, row: &Row) -> Vec<String> {
+    fn prepare(&self, sql: &str) -> Result<Statement<'_>, rusqlite::Error> {
+        self.prepared_sql.borrow_mut().push(sql.to_owned());
+        self.connection.prepare(sql)
+    }
+
+    pub fn clear_prepared_sql(&self) {
+        self.prepared_sql.borrow_mut().clear();
+    }
+
+    pub fn total_changes(&self) -> Result<i64, rusqlite::Error> {
+        // Rows written through this connection since it was opened; used to detect that a request wrote
+        self.connection
+            .query_row("SELECT total_changes();", [], |row| row.get(0))
+    }
+
+    pub fn explain_query_plan(&self) -> Result<ResultTable, DbError> {
+        // Returns the EXPLAIN QUERY PLAN output of every statement prepared since clear_prepared_sql()
+        // Unbound parameters are treated as NULL, which does not change the plan's shape
+        let mut rows: Vec<Vec<Value>> = vec![];
+        for (i, sql) in self.prepared_sql.borrow().iter().enumerate() {
+            let mut statement = self.connection.prepare(&format!("EXPLAIN QUERY PLAN {}", sql))?;
+            // raw_query, since the statement's parameters are deliberately left unbound
+            let mut plan = statement.raw_query();
+            while let Some(row) = plan.next()? {
+                rows.push(vec![
+                    Value::Integer(i as i64 + 1),
+                    Value::Text(sql.split_whitespace().collect::<Vec<&str>>().join(" ")),
+                    row.get::<_, Value>(0)?,
+                    row.get::<_, Value>(1)?,
+                    row.get::<_, Value>(3)?,
+                ]);
+            }
+        }
+        Ok(ResultTable::new(&["query", "sql", "id", "parent", "detail"], rows))
+    }
+
+    fn row_to_vec_value(n_columns: &usize, row: &Row) -> Vec<Value> {
         (0..*n_columns)
-            .map(|i: usize| match row.get_ref_unwrap(i) {
-                ValueRef::Null => "".to_owned(),
-                ValueRef::Integer(v) => v.to_string(),
-                ValueRef::Real(v) => v.to_string(),
-                ValueRef::Text(v) | ValueRef::Blob(v) => String::from_utf8(v.to_vec()).unwrap(),
-            })
-            .collect::<Vec<String>>()
+            .map(|i: usize| Value::from(row.get_ref_unwrap(i)))
+            .collect::<Vec<Value>>()
     }
 
-    fn rows_as_2d_vec_string(statement: &mut Statement) -> Vec<Vec<String>> {
+    fn rows_as_2d_vec_value<P: Params>(statement: &mut Statement, params: P) -> Result<Vec<Vec<Value>>, DbError> {
         let n_columns = statement.column_count();
-        statement
-            .query_map([], |row| Ok(DB::row_to_vec_string(&n_columns, row)))
-            .unwrap()
-            .map(Result::unwrap)
-            .collect()
+        Ok(statement
+            .query_map(params, |row| Ok(DB::row_to_vec_value(&n_columns, row)))?
+            .collect::<Result<_, rusqlite::Error>>()?)
     }
 
-    fn rows_to_string(statement: &mut Statement) -> String {
-        let rows: Vec<Vec<String>> = DB::rows_as_2d_vec_string(statement);
-        let col_names: Vec<&str> = statement.column_names();
-        DB::parsed_rows_to_string(&col_names, &rows)
+    fn rows_to_table<P: Params>(statement: &mut Statement, params: P) -> Result<ResultTable, DbError> {
+        let rows: Vec<Vec<Value>> = DB::rows_as_2d_vec_value(statement, params)?;
+        Ok(ResultTable::new(&statement.column_names(), rows))
     }
 
-    pub fn get_all_players(&self, name: Option<String>) -> Result<String, rusqlite::Error> {
-        // -> Result<String, rusqlite::Error> {
-        let mut statement: Statement;
-        if let Some(_name) = name {
-            let sql = format!("SELECT * FROM player WHERE player.name LIKE \"%{}%\"", _name);
-            statement = self.connection.prepare(&sql).unwrap();
-        } else {
-            statement = self.connection.prepare("SELECT * FROM player").unwrap();
+    fn validate_statistic(name: &str) -> Result<&'static str, DbError> {
+        // Statistic names are interpolated into SQL, so only exact column names are accepted
+        STATISTICS_COLUMNS
+            .iter()
+            .find(|c| **c == name.trim())
+            .copied()
+            .ok_or(DbError::InvalidArgument(format!("No such statistic: {}", name)))
+    }
+
+    fn parse_composite(terms: &[String]) -> Result<Vec<(&'static str, f64)>, DbError> {
+        // Parses a composite statistic, given as a list of "statistic" or "statistic:weight" terms,
+        // e.g.: ["goals", "assists:0.5"] -> goals + 0.5 * assists
+        if terms.is_empty() {
+            return Err(DbError::InvalidArgument("Empty composite statistic".to_owned()));
+        }
+        terms
+            .iter()
+            .map(|t| match t.split_once(':') {
+                Some((stat, weight)) => match weight.trim().parse::<f64>() {
+                    Ok(w) => Ok((DB::validate_statistic(stat)?, w)),
+                    Err(_) => Err(DbError::InvalidArgument(format!(
+                        "Invalid weight for statistic {}: {}",
+                        stat, weight
+                    ))),
+                },
+                None => Ok((DB::validate_statistic(t)?, 1.0)),
+            })
+            .collect()
+    }
+
+    pub fn get_all_players(&self, name: Option<String>, position: Option<String>) -> Result<ResultTable, DbError> {
+        let mut conditions: Vec<String> = vec![];
+        let mut params: Vec<(&str, &dyn ToSql)> = vec![];
+        let pattern = name.map(|n| format!("%{}%", n));
+        if let Some(pattern) = &pattern {
+            conditions.push("player.name LIKE :name".to_owned());
+            params.push((":name", pattern));
         }
-        Ok(DB::rows_to_string(&mut statement))
+        if let Some(position) = &position {
+            conditions.push(sub_position::POSITION_FILTER_SQL.to_owned());
+            params.push((":position", position));
+        }
+        let sql = if conditions.is_empty() {
+            "SELECT * FROM player".to_owned()
+        } else {
+            format!("SELECT * FROM player WHERE {}", conditions.join(" AND "))
+        };
+        let mut statement = self.prepare(&sql)?;
+        DB::rows_to_table(&mut statement, params.as_slice())
     }
 
     pub fn get_player(
         &self,
         player_id: Option<String>,
         statistics: Option<Vec<String>>,
-    ) -> Result<String, rusqlite::Error> {
-        let statistics_string = match statistics {
-            Some(s) => {
-                let mut statistics = s;
-                if !statistics.is_empty() {
-                    statistics
-                        .iter_mut()
-                        .for_each(|e: &mut String| e.insert_str(0, "statistics."));
-                    let mut statistics = statistics.join(",");
-                    statistics.insert(0, ',');
-                    statistics
-                } else {
-                    "".into()
-                }
-            }
-            None => "".into(),
+        position: Option<String>,
+    ) -> Result<ResultTable, DbError> {
+        let (statistics, explicit) = select_statistics(statistics)?;
+        let statistics_string = if !statistics.is_empty() {
+            let mut statistics = statistics.clone();
+            statistics
+                .iter_mut()
+                .for_each(|e: &mut String| e.insert_str(0, "statistics."));
+            let mut statistics = statistics.join(",");
+            statistics.insert(0, ',');
+            statistics
+        } else {
+            "".into()
         };
 
-        let where_pid_clause = match player_id {
-            Some(id) => format!("WHERE player.id = {id}"),
-            None => "".to_owned(),
+        let mut conditions: Vec<String> = vec![];
+        if let Some(id) = player_id {
+            let id: i64 = id
+                .parse()
+                .map_err(|_| DbError::InvalidArgument(format!("Invalid player_id: {}", id)))?;
+            conditions.push(format!("player.id = {id}"));
+        }
+        let mut params: Vec<(&str, &dyn ToSql)> = vec![];
+        if let Some(position) = &position {
+            conditions.push(sub_position::POSITION_FILTER_SQL.to_owned());
+            params.push((":position", position));
+        }
+        let where_clause = if conditions.is_empty() {
+            "".to_owned()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
         };
+        // JOIN_ALL yields one row per position, so positions are aggregated back into a single field
         let sql = format!(
-            "SELECT player.id, player.name, position.name as position {} FROM {} {};",
-            statistics_string, JOIN_ALL, where_pid_clause
+            "SELECT player.id, player.name, group_concat(position.name, '{0}') as position,
+            (SELECT group_concat(sub_position_name, '{0}') FROM player_sub_position
+                WHERE player_sub_position.player_id = player.id) as sub_position
+            {1} FROM {2} {3} GROUP BY player.id;",
+            POSITION_SEPARATOR, statistics_string, JOIN_ALL, where_clause
         );
         println!("Querying DB: {}", sql);
-        let mut statement = self.connection.prepare(&sql).unwrap();
-        Ok(DB::rows_to_string(&mut statement))
+        let mut statement = self.prepare(&sql)?;
+        let mut table = DB::rows_to_table(&mut statement, params.as_slice())?;
+        hide_irrelevant_statistics(&mut table, &explicit);
+        Ok(table)
     }
 }
-impl Default for DB {
-    fn default() -> Self {
-        Self::new()
+
+fn select_statistics(statistics: Option<Vec<String>>) -> Result<(Vec<String>, Vec<String>), DbError> {
+    // Returns the statistics get-player selects, and those of them that were named explicitly
+    // statistics=all selects every statistic, but each player's irrelevant ones are hidden (e.g. saves for forwards);
+    // statistics that are named explicitly are always shown
+    let mut statistics = statistics.unwrap_or_default();
+    let show_all = statistics.iter().any(|e| e == "all");
+    statistics.retain(|e| e != "all");
+    for statistic in &statistics {
+        DB::validate_statistic(statistic)?;
+    }
+    let explicit = statistics.clone();
+    if !show_all {
+        return Ok((statistics, explicit));
     }
+    statistics.extend(
+        STATISTICS_COLUMNS
+            .iter()
+            .filter(|c| !explicit.iter().any(|e| e == *c))
+            .map(|c| c.to_string()),
+    );
+    Ok((statistics, explicit))
 }
-fn read_lines(filename: &str) -> Vec<String> {
-    read_to_string(filename)
-        .unwrap() // panic on possible file-reading errors
+
+fn hide_irrelevant_statistics(table: &mut ResultTable, explicit: &[String]) {
+    // Expects get-player's columns: id, name, position, sub_position, then statistics
+    // A statistic irrelevant to all of a player's positions is shown as missing for that player, and dropped
+    // altogether if it is irrelevant to every returned player
+    let column_names = table.column_names.clone();
+    let hidden = |i: usize, c: &String| i >= 4 && !explicit.contains(c);
+    let mut shown: Vec<bool> = column_names.iter().enumerate().map(|(i, c)| !hidden(i, c)).collect();
+    for row in table.rows.iter_mut() {
+        // Players without a position are left as they are, as nothing is known to be irrelevant to them
+        let positions: Vec<String> = match &row[2] {
+            Value::Text(p) => p.split(POSITION_SEPARATOR).map(String::from).collect(),
+            _ => continue,
+        };
+        for (i, c) in column_names.iter().enumerate() {
+            if !hidden(i, c) {
+                continue;
+            }
+            match positions.iter().any(|p| statistic_relevant_to(c, p)) {
+                true => shown[i] = true,
+                false => row[i] = Value::Null,
+            }
+        }
+    }
+    table.retain_columns(&shown);
+}
+fn read_lines(filename: &str) -> Result<Vec<String>, ImportError> {
+    Ok(read_to_string(filename)
+        .map_err(|source| ImportError::Io {
+            path: filename.to_owned(),
+            source,
+        })?
         .lines() // split the string into an iterator of string slices
         .map(|e| e.trim().into())
-        .collect() // gather them together into a vector
+        .collect()) // gather them together into a vector
 }
 
 fn _get_ignored_columns(csv_header: &[String]) -> Vec<(usize, String)> {
@@ -227,13 +524,24 @@ fn _get_ignored_columns(csv_header: &[String]) -> Vec<(usize, String)> {
         .collect()
 }
 
-fn parse_csv() -> (Vec<String>, Vec<Vec<String>>) {
-    let mut lines: Vec<Vec<String>> = read_lines("soccer.csv")
+fn parse_csv() -> Result<(Vec<String>, Vec<Vec<String>>), ImportError> {
+    let mut lines: Vec<Vec<String>> = read_lines("soccer.csv")?
         .iter()
         .map(|e| e.split(',').map(|e| String::from(e.trim())).collect())
         .collect();
-
+    if lines.is_empty() {
+        return Err(ImportError::MalformedCsv("soccer.csv is empty".to_owned()));
+    }
     let mut csv_header = lines.remove(0);
+    // Columns are removed by index below, so every row must line up with the header
+    if let Some((i, line)) = lines.iter().enumerate().find(|(_, l)| l.len() != csv_header.len()) {
+        return Err(ImportError::MalformedCsv(format!(
+            "line {}: expected {} columns, found {}",
+            i + 2,
+            csv_header.len(),
+            line.len()
+        )));
+    }
     let ignore_columns = _get_ignored_columns(&csv_header);
     println!("IGNORE COLUMNS INDICES: {:?}", ignore_columns);
     for (csvh_offset, (idx, _)) in ignore_columns.iter().enumerate() {
@@ -247,7 +555,35 @@ fn parse_csv() -> (Vec<String>, Vec<Vec<String>>) {
             offset += 1;
         })
     });
-    (csv_header, lines)
+    Ok((csv_header, lines))
+}
+
+fn import_csv(policy: DuplicatePolicy) -> Result<(Vec<String>, Vec<Vec<String>>), ImportError> {
+    // Rows of soccer.csv as imported by either backend, with duplicate players resolved according to policy
+    let (header, data) = parse_csv()?;
+    let data = duplicates::resolve_duplicates(&header, data, policy);
+    Ok((header, data))
+}
+
+fn parse_cell(cell: &str) -> Result<Value, ImportError> {
+    let cell = cell.trim();
+    Ok(if cell.is_empty() {
+        // Blank cells are missing values, not zeroes
+        Value::Null
+    } else if let Ok(v) = cell.parse::<i64>() {
+        Value::Integer(v)
+    } else if let Ok(v) = cell.parse::<f64>() {
+        Value::Real(v)
+    } else if let Some(pct) = cell.strip_suffix('%') {
+        // Percentage into decimal - does not enforce the total number of digits denoted by n in SQL's Decimal(n, p),
+        // but does enforce p (# of digits after decimal)
+        let pct = pct
+            .parse::<f64>()
+            .map_err(|_| ImportError::MalformedCsv(format!("invalid percentage: {}", cell)))?;
+        Value::Real(pct.round() / 100.0)
+    } else {
+        Value::Text(cell.to_owned())
+    })
 }
 
 fn insert_all_into(
@@ -255,7 +591,10 @@ fn insert_all_into(
     table_name: TableName,
     attributes: &[&(TableName, &str)],
     data: &[Vec<String>],
-) -> Result<(), rusqlite::Error> {
+    player_ids: &[i64],
+) -> Result<Vec<i64>, ImportError> {
+    // Inserts one row per data row, returning the id of each inserted row
+    // player_ids (the ids returned when inserting into player) supply the foreign key for statistics and position
     // Get data_indices and respective attributes for table_name
     let (data_indices, attributes): (Vec<usize>, Vec<&(TableName, &str)>) =
         attributes.iter().enumerate().filter(|(_, e)| e.0 == table_name).unzip();
@@ -273,59 +612,83 @@ fn insert_all_into(
                 .collect()
         })
         .collect();
-    println!("DATA[0]: {:?}", data[0]);
+    println!("DATA[0]: {:?}", data.first());
 
     // Handle foreign key inserts
-    let player_ids = (1..=data.len()).map(|e| e.to_string()).collect::<Vec<String>>();
     match table_name {
         TableName::Player => (),
         TableName::Statistics | TableName::Position => {
             attributes.push("player_id");
             data.iter_mut()
-                .enumerate()
-                .for_each(|(i, e)| e.push(player_ids[i].to_owned()))
+                .zip(player_ids)
+                .for_each(|(e, player_id)| e.push(player_id.to_string()))
         }
     }
+    // Multi-position cells (e.g. "Defender/Midfielder") become one position row per position
+    // Sub-positions (e.g. "CB") are inserted as their broad position; see sub_position::insert_sub_positions
+    if table_name == TableName::Position {
+        let name_idx = match attributes.iter().position(|a| *a == "name") {
+            Some(idx) => idx,
+            None => return Err(ImportError::MalformedCsv("missing Position column".to_owned())),
+        };
+        data = data
+            .into_iter()
+            .flat_map(|row| {
+                let mut positions: Vec<String> = vec![];
+                for p in row[name_idx].split(POSITION_SEPARATOR).map(str::trim) {
+                    let p = sub_position::broad_position_of(p).unwrap_or(p).to_owned();
+                    if !positions.contains(&p) {
+                        positions.push(p);
+                    }
+                }
+                positions
+                    .into_iter()
+                    .map(|p| {
+                        let mut row = row.clone();
+                        row[name_idx] = p;
+                        row
+                    })
+                    .collect::<Vec<Vec<String>>>()
+            })
+            .collect();
+    }
 
-    let values_string: String = data // Parse VALUES entries; Prepare for SQL statement
+    let values_strings: Vec<String> = data // Parse VALUES entries; Prepare for SQL statements
         .iter()
         .map(|row| {
             let formatted: Vec<String> = row // Format each value in data row
                 .iter()
                 .map(|e| {
-                    let e = e.trim().to_owned();
-                    if e.is_empty() {
-                        "0".to_owned()
-                    } else if e.parse::<f64>().is_ok() {
-                        e.clone()
-                    } else if e.ends_with('%') {
-                        // Percentage into decimal - does not enforce the total number of digits denoted by n in SQL's Decimal(n, p),
-                        // but does enforce p (# of digits after decimal)
-                        format!("{:.2}", e[..e.len() - 1].parse::<f64>().unwrap() / 100.0)
-                    } else {
+                    Ok(match parse_cell(e)? {
+                        Value::Null => "NULL".to_owned(),
+                        Value::Integer(v) => v.to_string(),
+                        Value::Real(v) => v.to_string(),
                         // Stringy data
-                        format!("\"{}\"", e) // Add quotations for Stringy data
-                    }
+                        v => format!("\"{}\"", format::value_to_string(&v)), // Add quotations for Stringy data
+                    })
                 })
-                .collect();
-            format!("({})", formatted.join(",")) // Join each value in row with comma
+                .collect::<Result<_, ImportError>>()?;
+            Ok(format!("({})", formatted.join(","))) // Join each value in row with comma
         })
-        .collect::<Vec<String>>()
-        .join(",\n"); // Join each VALUES entry with comma-separator and newline
-
-    // Prepare final statement for execution
-    let statement = format!(
-        "INSERT INTO {}({}) VALUES {};",
-        table_name,
-        attributes.join(","),
-        values_string
-    );
-    println!("statement: {}", statement);
-    connection.execute(&statement, ()).unwrap();
-    Ok(())
+        .collect::<Result<_, ImportError>>()?;
+
+    // Rows are inserted one at a time so that each generated id can be returned
+    let mut ids: Vec<i64> = Vec::with_capacity(values_strings.len());
+    for values_string in values_strings {
+        let statement = format!(
+            "INSERT INTO {}({}) VALUES {};",
+            table_name,
+            attributes.join(","),
+            values_string
+        );
+        connection.execute(&statement, ())?;
+        ids.push(connection.last_insert_rowid());
+    }
+    println!("Inserted {} row(s) into {}", ids.len(), table_name);
+    Ok(ids)
 }
 
-pub fn csv_to_sqlite() {
+fn csv_to_db_attribute_map() -> HashMap<&'static str, (TableName, &'static str)> {
     let mut csv_to_db_attribute_map: HashMap<&str, (TableName, &'static str)> = HashMap::new();
     csv_to_db_attribute_map.extend([
         ("Name", (TableName::Player, "name")),
@@ -374,9 +737,26 @@ pub fn csv_to_sqlite() {
         ("Fouls", (TableName::Statistics, "fouls")),
         ("Offsides", (TableName::Statistics, "offsides")),
     ]);
+    csv_to_db_attribute_map
+}
+
+fn csv_attributes(header: &[String]) -> Result<Vec<(TableName, &'static str)>, ImportError> {
+    // Maps each csv column onto the table and attribute it is stored in
+    let mut csv_to_db_attribute_map = csv_to_db_attribute_map();
+    header
+        .iter()
+        .map(|a| {
+            csv_to_db_attribute_map
+                .remove(a.as_str())
+                .ok_or(ImportError::MalformedCsv(format!("unknown column: {}", a)))
+        })
+        .collect()
+}
+
+pub fn csv_to_sqlite(policy: DuplicatePolicy) -> Result<(), ImportError> {
     println!("Retrieving data from csv...");
-    let (header, data) = parse_csv();
-    println!("HEADER: {:#?}\nDATA HEAD: {:#?}", header, data[0]);
+    let (header, data) = import_csv(policy)?;
+    println!("HEADER: {:#?}\nDATA HEAD: {:#?}", header, data.first());
     let _path = Path::new("soccer.db");
     println!("Deleting old db file if exists...");
     if let Ok(()) = std::fs::remove_file(_path) {
@@ -388,22 +768,26 @@ pub fn csv_to_sqlite() {
     //     }
     //     Err(_) => (),
     // }
-    let connection = Connection::open("soccer.db").unwrap();
+    let connection = DB::open_connection("soccer.db")?;
     println!("Creating database tables...");
-    CREATE_TABLE_QUERIES.iter().for_each(|e| {
-        connection.execute(e, ()).unwrap();
-    });
+    for e in CREATE_TABLE_QUERIES {
+        connection.execute(e, ())?;
+    }
+    sub_position::seed_sub_positions(&connection)?;
+    println!("Creating database indexes...");
+    for e in CREATE_INDEX_QUERIES {
+        connection.execute(e, ())?;
+    }
     println!("Inserting data from csv into db tables...");
-    let attributes: Vec<&(TableName, &str)> = header
-        .iter()
-        .map(|a| csv_to_db_attribute_map.get(a.as_str()).unwrap())
-        .collect();
+    let attributes = csv_attributes(&header)?;
+    let attributes: Vec<&(TableName, &str)> = attributes.iter().collect();
 
-    // TODO: Need to add foreign key constraints:
-    // statistics.player_id -> player.id
-    // position.player_id -> player.id
-    // In order to do this, perhaps add a "universal_data" Option arg to insert_all_into that applies regardless of table_name restriction
-    insert_all_into(&connection, TableName::Player, &attributes, &data).unwrap();
-    insert_all_into(&connection, TableName::Statistics, &attributes, &data).unwrap();
-    insert_all_into(&connection, TableName::Position, &attributes, &data).unwrap();
+    // Foreign keys (statistics.player_id, position.player_id -> player.id) use the ids generated by the player inserts
+    let transaction = connection.unchecked_transaction()?;
+    let player_ids = insert_all_into(&connection, TableName::Player, &attributes, &data, &[])?;
+    insert_all_into(&connection, TableName::Statistics, &attributes, &data, &player_ids)?;
+    insert_all_into(&connection, TableName::Position, &attributes, &data, &player_ids)?;
+    sub_position::insert_sub_positions(&connection, &attributes, &data, &player_ids)?;
+    transaction.commit()?;
+    Ok(())
 }
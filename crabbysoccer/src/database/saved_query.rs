@@ -0,0 +1,43 @@
+use super::{format::ResultTable, DbError, DB};
+use rusqlite::{self, params, types::Value, OptionalExtension};
+
+pub fn validate_query_name(name: &str) -> Result<&str, DbError> {
+    // Saved query names are passed as a single client argument (e.g. "run-query young-strikers"), so cannot contain
+    // whitespace
+    let name = name.trim();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(DbError::InvalidArgument(format!("Invalid saved query name: {}", name)));
+    }
+    Ok(name)
+}
+
+pub fn saved_query_table(name: &str, query: &str) -> ResultTable {
+    ResultTable::new(
+        &["name", "query"],
+        vec![vec![Value::Text(name.to_owned()), Value::Text(query.to_owned())]],
+    )
+}
+
+impl DB {
+    pub fn save_query(&self, name: String, query: String) -> Result<ResultTable, DbError> {
+        // Saving under an existing name replaces that query
+        let name = validate_query_name(&name)?;
+        self.connection.execute(
+            "INSERT OR REPLACE INTO saved_query(name, query) VALUES (?1, ?2);",
+            params![name, query.trim()],
+        )?;
+        Ok(saved_query_table(name, query.trim()))
+    }
+
+    pub fn saved_query(&self, name: &str) -> Result<String, DbError> {
+        self.prepare("SELECT query FROM saved_query WHERE name = ?1;")?
+            .query_row([validate_query_name(name)?], |row| row.get(0))
+            .optional()?
+            .ok_or(DbError::NotFound(format!("No such saved query: {}", name)))
+    }
+
+    pub fn get_saved_queries(&self) -> Result<ResultTable, DbError> {
+        let mut statement = self.prepare("SELECT name, query FROM saved_query ORDER BY name;")?;
+        DB::rows_to_table(&mut statement, [])
+    }
+}
@@ -0,0 +1,283 @@
+use super::{
+    analytics, best_xi, fantasy, format::ResultTable, watchlist, DbError, MemoryStore, WatchlistEntry, DB,
+    POSITION_SEPARATOR,
+};
+use rusqlite::{self, named_params};
+
+// Environment variable selecting the server's storage backend: "sqlite" (default) or "memory"
+pub const BACKEND_ENV_VAR: &str = "CRABBYSOCCER_BACKEND";
+
+// One player, with the statistics requested from PlayerStore::player_statistics
+pub struct PlayerStatistics {
+    pub id: i64,
+    pub name: String,
+    pub club_name: Option<String>,
+    pub nationality: Option<String>,
+    pub age: Option<i64>,
+    pub positions: Vec<String>,
+    // Aligned with the requested statistics; None where the statistic is missing
+    pub values: Vec<Option<f64>>,
+}
+
+// Operations the server needs from a storage backend. Backends implement the lookups and writes; best-xi,
+// fantasy-points and the analytics endpoints are built on player_statistics, so they behave the same on every backend
+pub trait PlayerStore {
+    fn get_all_players(&self, name: Option<String>, position: Option<String>) -> Result<ResultTable, DbError>;
+
+    fn get_player(
+        &self,
+        player_id: Option<String>,
+        statistics: Option<Vec<String>>,
+        position: Option<String>,
+    ) -> Result<ResultTable, DbError>;
+
+    // Players with at least one position, matching the club and position (see POSITION_FILTER_SQL) filters, in id order
+    fn player_statistics(
+        &self,
+        club: Option<&str>,
+        position: Option<&str>,
+        statistics: &[&'static str],
+    ) -> Result<Vec<PlayerStatistics>, DbError>;
+
+    fn set_sub_positions(&self, player_id: String, sub_positions: Vec<String>) -> Result<ResultTable, DbError>;
+
+    fn validate(&self, rules: Option<Vec<String>>) -> Result<ResultTable, DbError>;
+
+    // Watchlists and notes are keyed by the client-supplied user name
+    fn create_watchlist(&self, user: String, list: String) -> Result<ResultTable, DbError>;
+
+    fn get_watchlists(&self, user: String) -> Result<ResultTable, DbError>;
+
+    fn add_to_watchlist(&self, user: String, list: String, player_ids: Vec<String>) -> Result<ResultTable, DbError>;
+
+    fn remove_from_watchlist(
+        &self,
+        user: String,
+        list: String,
+        player_ids: Vec<String>,
+    ) -> Result<ResultTable, DbError>;
+
+    fn set_note(
+        &self,
+        user: String,
+        player_id: String,
+        note: Option<String>,
+        tags: Vec<String>,
+    ) -> Result<ResultTable, DbError>;
+
+    // The list's players in the order they were added; NotFound if the user has no such list
+    fn watchlist_entries(&self, user: &str, list: &str) -> Result<Vec<WatchlistEntry>, DbError>;
+
+    // Saved queries are stored as given; the server checks them against AUTHORITATIVE_ENDPOINTS before saving
+    fn save_query(&self, name: String, query: String) -> Result<ResultTable, DbError>;
+
+    // NotFound if there is no query saved under name
+    fn saved_query(&self, name: &str) -> Result<String, DbError>;
+
+    fn get_saved_queries(&self) -> Result<ResultTable, DbError>;
+
+    // Writes made through this store since it was opened; used to detect that a request wrote
+    fn total_changes(&self) -> Result<i64, DbError>;
+
+    // explain=true support: clear_prepared_sql() is called before each request, explain_query_plan() after it
+    fn clear_prepared_sql(&self) {}
+
+    fn explain_query_plan(&self) -> Result<ResultTable, DbError> {
+        Err(DbError::Unsupported("explain requires the sqlite backend".to_owned()))
+    }
+
+    fn get_watchlist(
+        &self,
+        user: String,
+        list: String,
+        statistics: Option<Vec<String>>,
+    ) -> Result<ResultTable, DbError> {
+        watchlist::get_watchlist(self, user, list, statistics)
+    }
+
+    fn best_xi(&self, formation: String, club: Option<String>, score: Vec<String>) -> Result<ResultTable, DbError> {
+        best_xi::best_xi(self, formation, club, score)
+    }
+
+    fn fantasy_points(
+        &self,
+        ruleset: Option<String>,
+        club: Option<String>,
+        position: Option<String>,
+        limit: Option<String>,
+    ) -> Result<ResultTable, DbError> {
+        fantasy::fantasy_points(self, ruleset, club, position, limit)
+    }
+
+    fn aggregate(&self, group_by: String, stats: Vec<String>, function: String) -> Result<ResultTable, DbError> {
+        analytics::aggregate(self, group_by, stats, function)
+    }
+
+    fn distribution(
+        &self,
+        statistic: String,
+        position: Option<String>,
+        club: Option<String>,
+        bins: Option<String>,
+    ) -> Result<ResultTable, DbError> {
+        analytics::distribution(self, statistic, position, club, bins)
+    }
+
+    fn correlations(
+        &self,
+        statistics: Vec<String>,
+        method: Option<String>,
+        position: Option<String>,
+        club: Option<String>,
+        view: Option<String>,
+        top: Option<String>,
+    ) -> Result<ResultTable, DbError> {
+        analytics::correlations(self, statistics, method, position, club, view, top)
+    }
+}
+
+impl PlayerStore for DB {
+    fn get_all_players(&self, name: Option<String>, position: Option<String>) -> Result<ResultTable, DbError> {
+        DB::get_all_players(self, name, position)
+    }
+
+    fn get_player(
+        &self,
+        player_id: Option<String>,
+        statistics: Option<Vec<String>>,
+        position: Option<String>,
+    ) -> Result<ResultTable, DbError> {
+        DB::get_player(self, player_id, statistics, position)
+    }
+
+    fn player_statistics(
+        &self,
+        club: Option<&str>,
+        position: Option<&str>,
+        statistics: &[&'static str],
+    ) -> Result<Vec<PlayerStatistics>, DbError> {
+        let stats_string: String = statistics.iter().map(|s| format!(", statistics.{}", s)).collect();
+        let sql = format!(
+            "SELECT player.id, player.name, player.club_name, player.nationality, player.age,
+            group_concat(position.name, '{}') {}
+            FROM {} WHERE (:club IS NULL OR player.club_name = :club) AND (:position IS NULL OR {})
+            GROUP BY player.id ORDER BY player.id;",
+            POSITION_SEPARATOR,
+            stats_string,
+            super::JOIN_ALL,
+            super::sub_position::POSITION_FILTER_SQL
+        );
+        let mut statement = self.prepare(&sql)?;
+        let players = statement
+            .query_map(named_params! {":club": club, ":position": position}, |row| {
+                Ok(PlayerStatistics {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    club_name: row.get(2)?,
+                    nationality: row.get(3)?,
+                    age: row.get(4)?,
+                    positions: row
+                        .get::<_, String>(5)?
+                        .split(POSITION_SEPARATOR)
+                        .map(String::from)
+                        .collect(),
+                    values: (0..statistics.len())
+                        .map(|i| row.get(i + 6))
+                        .collect::<Result<_, rusqlite::Error>>()?,
+                })
+            })?
+            .collect::<Result<_, rusqlite::Error>>()?;
+        Ok(players)
+    }
+
+    fn set_sub_positions(&self, player_id: String, sub_positions: Vec<String>) -> Result<ResultTable, DbError> {
+        DB::set_sub_positions(self, player_id, sub_positions)
+    }
+
+    fn validate(&self, rules: Option<Vec<String>>) -> Result<ResultTable, DbError> {
+        DB::validate(self, rules)
+    }
+
+    fn create_watchlist(&self, user: String, list: String) -> Result<ResultTable, DbError> {
+        DB::create_watchlist(self, user, list)
+    }
+
+    fn get_watchlists(&self, user: String) -> Result<ResultTable, DbError> {
+        DB::get_watchlists(self, user)
+    }
+
+    fn add_to_watchlist(&self, user: String, list: String, player_ids: Vec<String>) -> Result<ResultTable, DbError> {
+        DB::add_to_watchlist(self, user, list, player_ids)
+    }
+
+    fn remove_from_watchlist(
+        &self,
+        user: String,
+        list: String,
+        player_ids: Vec<String>,
+    ) -> Result<ResultTable, DbError> {
+        DB::remove_from_watchlist(self, user, list, player_ids)
+    }
+
+    fn set_note(
+        &self,
+        user: String,
+        player_id: String,
+        note: Option<String>,
+        tags: Vec<String>,
+    ) -> Result<ResultTable, DbError> {
+        DB::set_note(self, user, player_id, note, tags)
+    }
+
+    fn watchlist_entries(&self, user: &str, list: &str) -> Result<Vec<WatchlistEntry>, DbError> {
+        DB::watchlist_entries(self, user, list)
+    }
+
+    fn save_query(&self, name: String, query: String) -> Result<ResultTable, DbError> {
+        DB::save_query(self, name, query)
+    }
+
+    fn saved_query(&self, name: &str) -> Result<String, DbError> {
+        DB::saved_query(self, name)
+    }
+
+    fn get_saved_queries(&self) -> Result<ResultTable, DbError> {
+        DB::get_saved_queries(self)
+    }
+
+    fn total_changes(&self) -> Result<i64, DbError> {
+        Ok(DB::total_changes(self)?)
+    }
+
+    fn clear_prepared_sql(&self) {
+        DB::clear_prepared_sql(self)
+    }
+
+    fn explain_query_plan(&self) -> Result<ResultTable, DbError> {
+        DB::explain_query_plan(self)
+    }
+}
+
+// Storage backend chosen at server startup; see BACKEND_ENV_VAR
+#[derive(Clone)]
+pub enum Backend {
+    // A connection to soccer.db is opened per client connection
+    Sqlite,
+    // Loaded from soccer.csv once and shared by every client connection; writes are lost on shutdown
+    Memory(MemoryStore),
+}
+impl Backend {
+    pub fn name(&self) -> &str {
+        match self {
+            Backend::Sqlite => "sqlite",
+            Backend::Memory(_) => "memory",
+        }
+    }
+
+    pub fn open(&self) -> Result<Box<dyn PlayerStore>, DbError> {
+        Ok(match self {
+            Backend::Sqlite => Box::new(DB::new()?),
+            Backend::Memory(store) => Box::new(store.clone()),
+        })
+    }
+}
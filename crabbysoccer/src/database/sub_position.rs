@@ -0,0 +1,164 @@
+use super::{format::ResultTable, DbError, TableName, DB, POSITION_SEPARATOR};
+use rusqlite::{self, params, Connection};
+
+// Format: (sub-position name, broad position name it falls under)
+pub const SUB_POSITIONS: [(&str, &str); 8] = [
+    ("GK", "Goalkeeper"),
+    ("CB", "Defender"),
+    ("FB", "Defender"),
+    ("DM", "Midfielder"),
+    ("CM", "Midfielder"),
+    ("AM", "Midfielder"),
+    ("W", "Forward"),
+    ("ST", "Forward"),
+];
+
+// Matches players that play position :position, which is either a broad position (which also matches every
+// sub-position under it) or a sub-position (matched exactly)
+pub const POSITION_FILTER_SQL: &str = "player.id IN (
+    SELECT position.player_id FROM position WHERE position.name = :position
+    UNION
+    SELECT player_sub_position.player_id FROM player_sub_position
+        JOIN sub_position ON sub_position.name = player_sub_position.sub_position_name
+        WHERE sub_position.name = :position OR sub_position.position_name = :position)";
+
+pub fn broad_position_of(sub_position: &str) -> Option<&'static str> {
+    SUB_POSITIONS
+        .iter()
+        .find(|(name, _)| *name == sub_position)
+        .map(|(_, position)| *position)
+}
+
+pub fn position_names() -> Vec<&'static str> {
+    // Every value a position= parameter can match: the broad positions, then the sub-positions
+    let mut names: Vec<&'static str> = vec![];
+    for (_, position) in SUB_POSITIONS {
+        if !names.contains(&position) {
+            names.push(position);
+        }
+    }
+    names.extend(sub_position_names());
+    names
+}
+
+pub fn sub_position_names() -> Vec<&'static str> {
+    SUB_POSITIONS.iter().map(|(name, _)| *name).collect()
+}
+
+pub fn plays_position(positions: &[String], sub_positions: &[String], position: &str) -> bool {
+    // In-memory equivalent of POSITION_FILTER_SQL
+    positions.iter().any(|p| p == position)
+        || sub_positions
+            .iter()
+            .any(|s| s == position || broad_position_of(s) == Some(position))
+}
+
+pub fn rebuild_positions(positions: &[String], old_sub_positions: &[String], sub_positions: &[String]) -> Vec<String> {
+    // Broad positions after replacing a player's sub-positions: those implied by the new sub-positions, plus those no
+    // old sub-position implied (e.g. "Defender" given on its own in the csv). If none are left, the old ones are kept,
+    // as every player has at least one position
+    let mut rebuilt: Vec<String> = positions
+        .iter()
+        .filter(|p| {
+            !old_sub_positions
+                .iter()
+                .any(|s| broad_position_of(s) == Some(p.as_str()))
+        })
+        .cloned()
+        .collect();
+    for position in sub_positions.iter().filter_map(|s| broad_position_of(s)) {
+        if !rebuilt.iter().any(|p| p == position) {
+            rebuilt.push(position.to_owned());
+        }
+    }
+    match rebuilt.is_empty() {
+        true => positions.to_vec(),
+        false => rebuilt,
+    }
+}
+
+pub fn seed_sub_positions(connection: &Connection) -> Result<(), rusqlite::Error> {
+    for (name, position) in SUB_POSITIONS {
+        connection.execute(
+            "INSERT INTO sub_position(name, position_name) VALUES (?1, ?2);",
+            params![name, position],
+        )?;
+    }
+    Ok(())
+}
+
+pub fn insert_sub_positions(
+    connection: &Connection,
+    attributes: &[&(TableName, &str)],
+    data: &[Vec<String>],
+    player_ids: &[i64],
+) -> Result<(), rusqlite::Error> {
+    // Sub-positions listed in the csv Position column (e.g. "CB/DM"); broad positions are inserted by insert_all_into
+    let position_idx = match attributes.iter().position(|a| a.0 == TableName::Position) {
+        Some(idx) => idx,
+        None => return Ok(()),
+    };
+    let mut inserted = 0;
+    for (row, player_id) in data.iter().zip(player_ids) {
+        for sub_position in row[position_idx]
+            .split(POSITION_SEPARATOR)
+            .map(str::trim)
+            .filter(|p| broad_position_of(p).is_some())
+        {
+            inserted += connection.execute(
+                "INSERT OR IGNORE INTO player_sub_position(player_id, sub_position_name) VALUES (?1, ?2);",
+                params![player_id, sub_position],
+            )?;
+        }
+    }
+    println!("Inserted {} row(s) into player_sub_position", inserted);
+    Ok(())
+}
+
+impl DB {
+    pub fn set_sub_positions(&self, player_id: String, sub_positions: Vec<String>) -> Result<ResultTable, DbError> {
+        // Replaces the player's sub-positions, and the broad positions they imply (see rebuild_positions)
+        let player_id: i64 = player_id
+            .parse()
+            .map_err(|_| DbError::InvalidArgument(format!("Invalid player_id: {}", player_id)))?;
+        for sub_position in &sub_positions {
+            if broad_position_of(sub_position).is_none() {
+                return Err(DbError::InvalidArgument(format!(
+                    "No such sub-position: {}",
+                    sub_position
+                )));
+            }
+        }
+        let transaction = self.connection.unchecked_transaction()?;
+        let exists: bool = self
+            .prepare("SELECT EXISTS(SELECT 1 FROM player WHERE id = ?1);")?
+            .query_row([player_id], |row| row.get(0))?;
+        if !exists {
+            return Err(DbError::NotFound(format!("No such player: {}", player_id)));
+        }
+        let names = |sql: &str| -> Result<Vec<String>, rusqlite::Error> {
+            self.prepare(sql)?.query_map([player_id], |row| row.get(0))?.collect()
+        };
+        let positions = rebuild_positions(
+            &names("SELECT name FROM position WHERE player_id = ?1;")?,
+            &names("SELECT sub_position_name FROM player_sub_position WHERE player_id = ?1;")?,
+            &sub_positions,
+        );
+        transaction.execute("DELETE FROM player_sub_position WHERE player_id = ?1;", [player_id])?;
+        transaction.execute("DELETE FROM position WHERE player_id = ?1;", [player_id])?;
+        for sub_position in &sub_positions {
+            transaction.execute(
+                "INSERT OR IGNORE INTO player_sub_position(player_id, sub_position_name) VALUES (?1, ?2);",
+                params![player_id, sub_position],
+            )?;
+        }
+        for position in &positions {
+            transaction.execute(
+                "INSERT INTO position(player_id, name) VALUES (?1, ?2);",
+                params![player_id, position],
+            )?;
+        }
+        transaction.commit()?;
+        self.get_player(Some(player_id.to_string()), None, None)
+    }
+}
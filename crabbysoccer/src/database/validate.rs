@@ -0,0 +1,178 @@
+use super::{
+    format::{value_to_string, ResultTable},
+    DbError, DB,
+};
+use rusqlite::{self, types::Value};
+
+// Looks up a player's statistic by column name, None if it is missing (NULL)
+type StatisticLookup<'a> = &'a dyn Fn(&str) -> Option<f64>;
+
+pub struct ValidationRule {
+    name: &'static str,
+    description: &'static str,
+    // WHERE clause over player JOIN statistics selecting the offending rows
+    condition: &'static str,
+    // In-memory equivalent of condition, given a statistic lookup and the player's broad positions
+    pub offends: fn(StatisticLookup<'_>, &[String]) -> bool,
+    // Statistics columns reported alongside each offending row
+    pub columns: &'static [&'static str],
+}
+
+// SQL comparison semantics for offends: a comparison with a missing (NULL) value is never true
+fn greater(lhs: Option<f64>, rhs: Option<f64>) -> bool {
+    matches!((lhs, rhs), (Some(l), Some(r)) if l > r)
+}
+
+fn sum_or_zero(statistic: StatisticLookup<'_>, columns: &[&str]) -> f64 {
+    // As a sum of IFNULL(column, 0) terms
+    columns.iter().map(|c| statistic(c).unwrap_or(0.0)).sum()
+}
+
+const VALIDATION_RULES: [ValidationRule; 6] = [
+    ValidationRule {
+        name: "results-within-appearances",
+        description: "wins + losses <= appearances",
+        condition: "statistics.wins + statistics.losses > statistics.appearances",
+        offends: |s, _| {
+            let results = s("wins").zip(s("losses")).map(|(w, l)| w + l);
+            greater(results, s("appearances"))
+        },
+        columns: &["wins", "losses", "appearances"],
+    },
+    ValidationRule {
+        name: "goal-types-within-goals",
+        description: "headed_goals + goals_left_foot + goals_right_foot <= goals",
+        condition: "IFNULL(statistics.headed_goals, 0) + IFNULL(statistics.goals_left_foot, 0)
+            + IFNULL(statistics.goals_right_foot, 0) > statistics.goals",
+        offends: |s, _| {
+            greater(
+                Some(sum_or_zero(s, &["headed_goals", "goals_left_foot", "goals_right_foot"])),
+                s("goals"),
+            )
+        },
+        columns: &["headed_goals", "goals_left_foot", "goals_right_foot", "goals"],
+    },
+    ValidationRule {
+        name: "set-pieces-within-goals",
+        description: "goals_from_penalties + goals_from_freekicks <= goals",
+        condition: "IFNULL(statistics.goals_from_penalties, 0) + IFNULL(statistics.goals_from_freekicks, 0)
+            > statistics.goals",
+        offends: |s, _| greater(Some(sum_or_zero(s, &["goals_from_penalties", "goals_from_freekicks"])), s("goals")),
+        columns: &["goals_from_penalties", "goals_from_freekicks", "goals"],
+    },
+    ValidationRule {
+        name: "shots-on-target-within-shots",
+        description: "shots_on_target <= shots",
+        condition: "statistics.shots_on_target > statistics.shots",
+        offends: |s, _| greater(s("shots_on_target"), s("shots")),
+        columns: &["shots_on_target", "shots"],
+    },
+    ValidationRule {
+        name: "pct-within-unit-interval",
+        description: "*_pct columns within [0, 1]",
+        condition: "statistics.shooting_accuracy_pct NOT BETWEEN 0 AND 1
+            OR statistics.tackle_success_pct NOT BETWEEN 0 AND 1
+            OR statistics.cross_accuracy_pct NOT BETWEEN 0 AND 1",
+        offends: |s, _| {
+            ["shooting_accuracy_pct", "tackle_success_pct", "cross_accuracy_pct"]
+                .iter()
+                .any(|c| s(c).is_some_and(|v| !(0.0..=1.0).contains(&v)))
+        },
+        columns: &["shooting_accuracy_pct", "tackle_success_pct", "cross_accuracy_pct"],
+    },
+    ValidationRule {
+        name: "goalkeeper-stats-on-outfield-player",
+        description: "goalkeeper-only statistics are missing for players without the Goalkeeper position",
+        condition: "(statistics.saves IS NOT NULL OR statistics.penalties_saved IS NOT NULL
+            OR statistics.punches IS NOT NULL OR statistics.high_claims IS NOT NULL OR statistics.catches IS NOT NULL
+            OR statistics.throw_outs IS NOT NULL OR statistics.goal_kicks IS NOT NULL)
+            AND NOT EXISTS (SELECT 1 FROM position WHERE position.player_id = player.id AND position.name = 'Goalkeeper')",
+        offends: |s, positions| {
+            super::GOALKEEPER_ONLY_STATISTICS.iter().any(|c| s(c).is_some()) && !positions.iter().any(|p| p == "Goalkeeper")
+        },
+        columns: &super::GOALKEEPER_ONLY_STATISTICS,
+    },
+];
+
+pub fn validation_rule_names() -> Vec<&'static str> {
+    VALIDATION_RULES.iter().map(|r| r.name).collect()
+}
+
+pub fn select_rules(rules: Option<Vec<String>>) -> Result<Vec<&'static ValidationRule>, DbError> {
+    // Every consistency rule, or only those named in rules
+    match rules {
+        Some(names) => names
+            .iter()
+            .map(|n| {
+                VALIDATION_RULES
+                    .iter()
+                    .find(|r| r.name == n)
+                    .ok_or(DbError::InvalidArgument(format!("No such validation rule: {}", n)))
+            })
+            .collect(),
+        None => Ok(VALIDATION_RULES.iter().collect()),
+    }
+}
+
+pub fn offending_row(rule: &ValidationRule, id: Value, name: Value, values: &[Value]) -> Vec<Value> {
+    // A row of the validate report; values are aligned with rule.columns
+    let details: Vec<String> = rule
+        .columns
+        .iter()
+        .zip(values)
+        .map(|(c, v)| format!("{}={}", c, value_to_string(v)))
+        .collect();
+    vec![
+        Value::Text(rule.name.to_owned()),
+        id,
+        name,
+        Value::Text(details.join(", ")),
+    ]
+}
+
+pub const VALIDATION_COLUMNS: [&str; 4] = ["rule", "id", "name", "details"];
+
+impl DB {
+    fn validate_rule(&self, rule: &ValidationRule) -> Result<Vec<Vec<Value>>, rusqlite::Error> {
+        let columns_string: String = rule.columns.iter().map(|c| format!(", statistics.{}", c)).collect();
+        let sql = format!(
+            "SELECT player.id, player.name {} FROM player JOIN statistics ON player.id = statistics.player_id
+            WHERE {} ORDER BY player.id;",
+            columns_string, rule.condition
+        );
+        let mut statement = self.prepare(&sql)?;
+        let n_columns = statement.column_count();
+        let offending = statement
+            .query_map([], |row| Ok(DB::row_to_vec_value(&n_columns, row)))?
+            .collect::<Result<Vec<Vec<Value>>, rusqlite::Error>>()?;
+        Ok(offending
+            .into_iter()
+            .map(|row| offending_row(rule, row[0].clone(), row[1].clone(), &row[2..]))
+            .collect())
+    }
+
+    pub fn validate(&self, rules: Option<Vec<String>>) -> Result<ResultTable, DbError> {
+        // Runs every consistency rule (or only those named in rules) and reports each offending row
+        let mut rows: Vec<Vec<Value>> = vec![];
+        for rule in select_rules(rules)? {
+            rows.extend(self.validate_rule(rule)?);
+        }
+        Ok(ResultTable::new(&VALIDATION_COLUMNS, rows))
+    }
+}
+
+pub fn summarize_validation(report: &ResultTable) -> String {
+    // Offending row count per rule, including rules that passed
+    VALIDATION_RULES
+        .iter()
+        .map(|rule| {
+            let count = report
+                .rows
+                .iter()
+                .filter(|r| matches!(&r[0], Value::Text(name) if name == rule.name))
+                .count();
+            format!("{} ({}): {} offending row(s)", rule.name, rule.description, count)
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
@@ -0,0 +1,215 @@
+use super::{format::ResultTable, hide_irrelevant_statistics, select_statistics, DbError, PlayerStore, DB};
+use rusqlite::{self, params, types::Value, OptionalExtension};
+
+// Separates the tags of a player note in storage and output
+pub const TAG_SEPARATOR: char = '/';
+
+// A player on a watchlist, with the list owner's note on them (if any)
+pub struct WatchlistEntry {
+    pub player_id: i64,
+    pub note: Option<String>,
+    pub tags: Option<String>,
+}
+
+pub fn validate_name<'a>(kind: &str, name: &'a str) -> Result<&'a str, DbError> {
+    // User and watchlist names are free text, but must not be blank
+    match name.trim() {
+        "" => Err(DbError::InvalidArgument(format!("{} must not be blank", kind))),
+        name => Ok(name),
+    }
+}
+
+pub fn parse_player_ids(player_ids: &[String]) -> Result<Vec<i64>, DbError> {
+    player_ids
+        .iter()
+        .map(|id| {
+            id.parse()
+                .map_err(|_| DbError::InvalidArgument(format!("Invalid player_id: {}", id)))
+        })
+        .collect()
+}
+
+pub fn join_tags(tags: &[String]) -> Option<String> {
+    let tags: Vec<&str> = tags.iter().map(|t| t.trim()).filter(|t| !t.is_empty()).collect();
+    match tags.is_empty() {
+        true => None,
+        false => Some(tags.join(&TAG_SEPARATOR.to_string())),
+    }
+}
+
+pub fn note_table(player_id: i64, note: Option<String>, tags: Option<String>) -> ResultTable {
+    ResultTable::new(
+        &["player_id", "note", "tags"],
+        vec![vec![
+            Value::Integer(player_id),
+            note.map_or(Value::Null, Value::Text),
+            tags.map_or(Value::Null, Value::Text),
+        ]],
+    )
+}
+
+pub fn get_watchlist<S: PlayerStore + ?Sized>(
+    store: &S,
+    user: String,
+    list: String,
+    statistics: Option<Vec<String>>,
+) -> Result<ResultTable, DbError> {
+    // get-player's output for the list's players, in the order they were added, followed by the user's note on each
+    let entries = store.watchlist_entries(&user, &list)?;
+    let (statistics, explicit) = select_statistics(statistics)?;
+    let mut table = store.get_player(None, Some(statistics), None)?;
+    let mut rows: Vec<Vec<Value>> = vec![];
+    for entry in entries {
+        // Players without a position are absent from get-player, so cannot be listed
+        let row = table.rows.iter().find(|r| r[0] == Value::Integer(entry.player_id));
+        if let Some(row) = row {
+            let mut row = row.clone();
+            row.push(entry.note.map_or(Value::Null, Value::Text));
+            row.push(entry.tags.map_or(Value::Null, Value::Text));
+            rows.push(row);
+        }
+    }
+    table.rows = rows;
+    table.column_names.extend(["note".to_owned(), "tags".to_owned()]);
+    // Relevance is judged on the listed players only, so it is applied after filtering
+    let mut explicit = explicit;
+    explicit.extend(["note".to_owned(), "tags".to_owned()]);
+    hide_irrelevant_statistics(&mut table, &explicit);
+    Ok(table)
+}
+
+impl DB {
+    fn watchlist_id(&self, user: &str, list: &str) -> Result<i64, DbError> {
+        let (user, list) = (validate_name("user", user)?, validate_name("list", list)?);
+        self.prepare("SELECT id FROM watchlist WHERE user_name = ?1 AND name = ?2;")?
+            .query_row(params![user, list], |row| row.get(0))
+            .optional()?
+            .ok_or(DbError::NotFound(format!("No such watchlist: {}", list)))
+    }
+
+    fn ensure_players_exist(&self, player_ids: &[i64]) -> Result<(), DbError> {
+        for player_id in player_ids {
+            let exists: bool = self
+                .prepare("SELECT EXISTS(SELECT 1 FROM player WHERE id = ?1);")?
+                .query_row([player_id], |row| row.get(0))?;
+            if !exists {
+                return Err(DbError::NotFound(format!("No such player: {}", player_id)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn create_watchlist(&self, user: String, list: String) -> Result<ResultTable, DbError> {
+        let (user, list) = (validate_name("user", &user)?, validate_name("list", &list)?);
+        let inserted = self.connection.execute(
+            "INSERT OR IGNORE INTO watchlist(user_name, name) VALUES (?1, ?2);",
+            params![user, list],
+        )?;
+        if inserted == 0 {
+            return Err(DbError::InvalidArgument(format!("Watchlist already exists: {}", list)));
+        }
+        self.get_watchlists(user.to_owned())
+    }
+
+    pub fn get_watchlists(&self, user: String) -> Result<ResultTable, DbError> {
+        let mut statement = self.prepare(
+            "SELECT watchlist.name, count(watchlist_player.player_id) as players FROM watchlist
+            LEFT JOIN watchlist_player ON watchlist_player.watchlist_id = watchlist.id
+            WHERE watchlist.user_name = ?1 GROUP BY watchlist.id ORDER BY watchlist.name;",
+        )?;
+        DB::rows_to_table(&mut statement, [validate_name("user", &user)?])
+    }
+
+    pub fn add_to_watchlist(
+        &self,
+        user: String,
+        list: String,
+        player_ids: Vec<String>,
+    ) -> Result<ResultTable, DbError> {
+        // Players already on the list are left where they are
+        let watchlist_id = self.watchlist_id(&user, &list)?;
+        let player_ids = parse_player_ids(&player_ids)?;
+        self.ensure_players_exist(&player_ids)?;
+        let transaction = self.connection.unchecked_transaction()?;
+        for player_id in &player_ids {
+            transaction.execute(
+                "INSERT OR IGNORE INTO watchlist_player(watchlist_id, player_id) VALUES (?1, ?2);",
+                params![watchlist_id, player_id],
+            )?;
+        }
+        transaction.commit()?;
+        get_watchlist(self, user, list, None)
+    }
+
+    pub fn remove_from_watchlist(
+        &self,
+        user: String,
+        list: String,
+        player_ids: Vec<String>,
+    ) -> Result<ResultTable, DbError> {
+        let watchlist_id = self.watchlist_id(&user, &list)?;
+        let player_ids = parse_player_ids(&player_ids)?;
+        let transaction = self.connection.unchecked_transaction()?;
+        for player_id in &player_ids {
+            let removed = transaction.execute(
+                "DELETE FROM watchlist_player WHERE watchlist_id = ?1 AND player_id = ?2;",
+                params![watchlist_id, player_id],
+            )?;
+            if removed == 0 {
+                return Err(DbError::NotFound(format!(
+                    "Player {} is not on watchlist {}",
+                    player_id, list
+                )));
+            }
+        }
+        transaction.commit()?;
+        get_watchlist(self, user, list, None)
+    }
+
+    pub fn set_note(
+        &self,
+        user: String,
+        player_id: String,
+        note: Option<String>,
+        tags: Vec<String>,
+    ) -> Result<ResultTable, DbError> {
+        // Replaces the user's note and tags on the player; a blank note with no tags removes it
+        let user = validate_name("user", &user)?;
+        let player_id = parse_player_ids(&[player_id])?[0];
+        self.ensure_players_exist(&[player_id])?;
+        let note = note.map(|n| n.trim().to_owned()).filter(|n| !n.is_empty());
+        let tags = join_tags(&tags);
+        if note.is_none() && tags.is_none() {
+            self.connection.execute(
+                "DELETE FROM player_note WHERE user_name = ?1 AND player_id = ?2;",
+                params![user, player_id],
+            )?;
+        } else {
+            self.connection.execute(
+                "INSERT OR REPLACE INTO player_note(user_name, player_id, note, tags) VALUES (?1, ?2, ?3, ?4);",
+                params![user, player_id, note, tags],
+            )?;
+        }
+        Ok(note_table(player_id, note, tags))
+    }
+
+    pub fn watchlist_entries(&self, user: &str, list: &str) -> Result<Vec<WatchlistEntry>, DbError> {
+        let watchlist_id = self.watchlist_id(user, list)?;
+        let user = validate_name("user", user)?;
+        let mut statement = self.prepare(
+            "SELECT watchlist_player.player_id, player_note.note, player_note.tags FROM watchlist_player
+            LEFT JOIN player_note ON player_note.player_id = watchlist_player.player_id AND player_note.user_name = ?1
+            WHERE watchlist_player.watchlist_id = ?2 ORDER BY watchlist_player.rowid;",
+        )?;
+        let entries = statement
+            .query_map(params![user, watchlist_id], |row| {
+                Ok(WatchlistEntry {
+                    player_id: row.get(0)?,
+                    note: row.get(1)?,
+                    tags: row.get(2)?,
+                })
+            })?
+            .collect::<Result<_, rusqlite::Error>>()?;
+        Ok(entries)
+    }
+}
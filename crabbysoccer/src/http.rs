@@ -0,0 +1,140 @@
+use std::io::{self, BufRead, Read};
+
+// Line terminator of the request/status line and headers; a blank line ends the head of a message
+pub const CRLF: &str = "\r\n";
+
+// Header a client may label a request with; the server echoes it on the response, so responses can be matched to
+// the requests that caused them however many are in flight
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+// Requests heads longer than this are rejected, so a client cannot make the server buffer without bound
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+
+// Format: (status code, reason phrase)
+const STATUS_DEFS: [(u16, &str); 8] = [
+    (200, "OK"),
+    (400, "Bad Request"),
+    (404, "Not Found"),
+    (405, "Method Not Allowed"),
+    (431, "Request Header Fields Too Large"),
+    (500, "Internal Server Error"),
+    (501, "Not Implemented"),
+    (505, "HTTP Version Not Supported"),
+];
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    STATUS_DEFS
+        .iter()
+        .find(|(code, _)| *code == status)
+        .map_or("Unknown", |(_, reason)| reason)
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+    // Extra headers, e.g. Allow on 405 responses
+    pub headers: Vec<(&'static str, String)>,
+}
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: String) -> Self {
+        Response {
+            status,
+            content_type,
+            body,
+            headers: vec![],
+        }
+    }
+
+    pub fn to_http(&self, keep_alive: bool, include_body: bool) -> String {
+        // HEAD responses carry the headers a GET would, including Content-Length, but no body
+        let mut head = format!(
+            "HTTP/1.1 {} {}{CRLF}Content-Type: {}; charset=utf-8{CRLF}Content-Length: {}{CRLF}Connection: {}{CRLF}",
+            self.status,
+            reason_phrase(self.status),
+            self.content_type,
+            self.body.len(),
+            if keep_alive { "keep-alive" } else { "close" },
+        );
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}{CRLF}", name, value));
+        }
+        head.push_str(CRLF);
+        if include_body {
+            head.push_str(&self.body);
+        }
+        head
+    }
+}
+
+pub enum HeadError {
+    Io(io::Error),
+    TooLarge,
+}
+
+pub fn read_head<R: BufRead>(reader: &mut R) -> Result<Option<Vec<String>>, HeadError> {
+    // Reads a message head: the request/status line and headers, up to the blank line ending them
+    // Bare LF line endings are accepted as well as CRLF; None if the peer closed the connection first
+    let mut lines: Vec<String> = vec![];
+    let mut read = 0;
+    loop {
+        let mut line: Vec<u8> = vec![];
+        let n = reader
+            .by_ref()
+            .take((MAX_HEAD_BYTES - read + 1) as u64)
+            .read_until(b'\n', &mut line)
+            .map_err(HeadError::Io)?;
+        read += n;
+        if read > MAX_HEAD_BYTES {
+            return Err(HeadError::TooLarge);
+        }
+        if n == 0 {
+            return match lines.is_empty() {
+                true => Ok(None),
+                false => Err(HeadError::Io(io::ErrorKind::UnexpectedEof.into())),
+            };
+        }
+        // Invalid UTF-8 is replaced rather than rejected, so it surfaces as a malformed request instead
+        let line = String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_owned();
+        if line.is_empty() {
+            // Blank lines before the request line are ignored, as RFC 9112 recommends
+            if lines.is_empty() {
+                continue;
+            }
+            return Ok(Some(lines));
+        }
+        lines.push(line);
+    }
+}
+
+pub fn header<'a>(head: &'a [String], name: &str) -> Option<&'a str> {
+    // Value of the first header called name (case-insensitive); the first line of head is the request/status line
+    head.iter().skip(1).find_map(|header| {
+        let (header_name, value) = header.split_once(':')?;
+        match header_name.trim().eq_ignore_ascii_case(name) {
+            true => Some(value.trim()),
+            false => None,
+        }
+    })
+}
+
+pub fn content_length(head: &[String]) -> Option<usize> {
+    header(head, "content-length")?.parse().ok()
+}
+
+pub fn take_response(buf: &mut Vec<u8>) -> Option<(Vec<String>, String)> {
+    // Removes the first complete response from buf, returning its head and body; None until one has fully arrived
+    let head_end = buf.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head: Vec<String> = String::from_utf8_lossy(&buf[..head_end])
+        .split(CRLF)
+        .map(String::from)
+        .collect();
+    let body_start = head_end + 4;
+    let body_end = body_start + content_length(&head).unwrap_or(0);
+    if buf.len() < body_end {
+        return None;
+    }
+    let body = String::from_utf8_lossy(&buf[body_start..body_end]).into_owned();
+    buf.drain(..body_end);
+    Some((head, body))
+}
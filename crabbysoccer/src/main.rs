@@ -1,8 +1,11 @@
+mod cache;
 mod client;
 mod common;
 pub mod database;
+mod http;
 mod requests;
 mod server;
+mod version;
 
 #[derive(PartialEq)]
 enum ApplicationType {
@@ -1,93 +1,990 @@
+use crate::{
+    database::{
+        self,
+        format::{unescape_text, ResultTable, TEXT_SEPARATOR},
+        MISSING_VALUE,
+    },
+    http::CRLF,
+    version::{self, CURRENT_VERSION},
+};
+use rusqlite::types::Value;
 use std::collections::HashMap;
 
-// ‘/get-player?player_id={player_id}&statistics={goals, assists, etc…}’:
-// ‘/get-all-players?name={name}’
+// ‘/get-player?player_id={player_id}&statistics={goals, assists, etc…}&position={position}’:
+// ‘/get-all-players?name={name}&position={position}’
+// ‘/best-xi?formation={4-3-3}&club={club}&score={goals, assists:0.5, etc…}’
+// ‘/fantasy-points?ruleset={ruleset}&club={club}&position={position}&limit={limit}’
+// ‘/validate?rule={results-within-appearances, shots-on-target-within-shots, etc…}’
+// ‘/set-sub-positions?player_id={player_id}&sub_positions={CB, FB, etc…}’
+// ‘/aggregate?group_by={club|position|nationality|age_bucket}&stats={goals, assists, etc…}&fn={sum|avg|min|max|median}’
+// ‘/distribution?statistic={goals}&position={position}&club={club}&bins={bins}’
+// ‘/correlations?statistics={goals, assists, etc…|all}&method={pearson|spearman}&position={position}&club={club}&view={matrix|pairs}&top={top}’
+// ‘/create-watchlist?user={user}&list={list}’
+// ‘/get-watchlists?user={user}’
+// ‘/get-watchlist?user={user}&list={list}&statistics={goals, assists, etc…|all}’
+// ‘/add-to-watchlist?user={user}&list={list}&player_id={player_id, etc…}’
+// ‘/remove-from-watchlist?user={user}&list={list}&player_id={player_id, etc…}’
+// ‘/set-note?user={user}&player_id={player_id}&note={note}&tags={tag, etc…}’
+// ‘/save-query?name={name}&query={get-player position={position} statistics=goals,assists}’
+// ‘/run-query?name={name}&{placeholder}={value}’
+// ‘/get-saved-queries’
+// ‘/endpoints’
+// ‘/handshake?versions={v1, v0, etc…}’
+// Each may be prefixed by an API version, e.g. ‘/v1/get-player?player_id={player_id}’; see version.rs
 
 pub type QueryPVMap = HashMap<String, Vec<String>>;
+pub type HeaderMap = HashMap<String, String>;
 
-pub const REQUEST_TERMINATOR: u8 = 0x0D;
+// Separates the values of a list parameter, e.g. "statistics=goals,assists"; a value containing one escapes it with
+// LIST_ESCAPE in the client CLI and saved queries, and is percent-encoded in URIs
+pub const LIST_SEPARATOR: char = ',';
+pub const LIST_ESCAPE: char = '\\';
+
+pub fn percent_encode(s: &str) -> String {
+    // RFC 3986: every byte of the UTF-8 encoding other than an unreserved character is written as %XX
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => char::from(b).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+pub fn percent_decode(s: &str) -> Result<String, ProtocolError> {
+    // '+' is left as is: it only means a space in HTML form encoding, which no client of the server uses
+    let invalid = || ProtocolError::InvalidPercentEncoding(s.to_owned());
+    let mut bytes: Vec<u8> = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        if b != b'%' {
+            bytes.push(b);
+            continue;
+        }
+        // Checked digit by digit, as from_str_radix would also accept a leading sign (e.g. "%+F")
+        let hex = rest
+            .get(..2)
+            .filter(|h| h.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .ok_or_else(invalid)?;
+        bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+        rest = &rest[2..];
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+fn split_list(values: &str) -> Vec<String> {
+    // Splits on every LIST_SEPARATOR not preceded by LIST_ESCAPE; escaped separators are unescaped, and other
+    // occurrences of LIST_ESCAPE are kept as they are
+    let mut list = vec![String::new()];
+    let mut chars = values.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            LIST_ESCAPE if chars.peek() == Some(&LIST_SEPARATOR) => {
+                list.last_mut().unwrap().push(chars.next().unwrap())
+            }
+            LIST_SEPARATOR => list.push(String::new()),
+            c => list.last_mut().unwrap().push(c),
+        }
+    }
+    list
+}
+
+// Quote characters of client command lines; either may be used, so a quoted value can contain the other
+// (e.g. query='get-all-players name="Bukayo Saka"')
+const QUOTES: [char; 2] = ['"', '\''];
+
+pub fn split_args(input: &str) -> Vec<String> {
+    // Splits a client command line (<COMMAND> <PARAMETER>=<VALUES> ...) on whitespace outside quotes, so a quoted
+    // value may contain spaces; quotes are kept, for parse_values to recognise
+    let mut args: Vec<String> = vec![];
+    let mut arg = String::new();
+    let mut quote: Option<char> = None;
+    for c in input.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            None if QUOTES.contains(&c) => quote = Some(c),
+            None if c.is_whitespace() => {
+                if !arg.is_empty() {
+                    args.push(std::mem::take(&mut arg));
+                }
+                continue;
+            }
+            _ => (),
+        }
+        arg.push(c);
+    }
+    if !arg.is_empty() {
+        args.push(arg);
+    }
+    args
+}
+
+pub fn parse_values(values: &str) -> Vec<String> {
+    // A quoted value is taken whole; otherwise LIST_SEPARATOR separates values, and LIST_ESCAPE escapes it
+    let quoted = QUOTES
+        .iter()
+        .any(|q| values.len() >= 2 && values.starts_with(*q) && values.ends_with(*q));
+    match quoted {
+        true => vec![values[1..values.len() - 1].to_owned()],
+        false => split_list(values),
+    }
+}
+
+// Parameters every endpoint accepts on top of its own, as they apply to the response rather than the request
+pub const GLOBAL_PARAMETERS: [&str; 2] = ["format", "explain"];
+
+// Endpoints that accept parameters beyond their schema: run-query takes one per placeholder of the saved query
+const OPEN_URIS: [&str; 1] = ["run-query"];
+
+// Endpoints that write to the database. explain=true is rejected on them, as the query plan is only gathered by
+// running the request, writes included
+pub const WRITE_URIS: [&str; 6] = [
+    "set-sub-positions",
+    "create-watchlist",
+    "add-to-watchlist",
+    "remove-from-watchlist",
+    "set-note",
+    "save-query",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParameterType {
+    Int,
+    Text,
+    // One of a fixed set of names, e.g. aggregate's fn
+    Enum(Vec<String>),
+    // A statistics column name; the names include "all" where it is accepted
+    Statistic(Vec<String>),
+    // A statistic name with an optional weight, e.g. "assists:0.5"
+    WeightedStatistic(Vec<String>),
+}
+impl ParameterType {
+    pub fn name(&self) -> &'static str {
+        // As listed by the endpoints endpoint
+        match self {
+            ParameterType::Int => "int",
+            ParameterType::Text => "text",
+            ParameterType::Enum(_) => "enum",
+            ParameterType::Statistic(_) => "statistic",
+            ParameterType::WeightedStatistic(_) => "weighted-statistic",
+        }
+    }
+
+    pub fn from_name(name: &str, allowed_values: Vec<String>) -> Option<Self> {
+        match name {
+            "int" => Some(ParameterType::Int),
+            "text" => Some(ParameterType::Text),
+            "enum" => Some(ParameterType::Enum(allowed_values)),
+            "statistic" => Some(ParameterType::Statistic(allowed_values)),
+            "weighted-statistic" => Some(ParameterType::WeightedStatistic(allowed_values)),
+            _ => None,
+        }
+    }
+
+    pub fn allowed_values(&self) -> &[String] {
+        // Empty for types that accept any value of their kind
+        match self {
+            ParameterType::Int | ParameterType::Text => &[],
+            ParameterType::Enum(names) | ParameterType::Statistic(names) | ParameterType::WeightedStatistic(names) => {
+                names
+            }
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        // What a value must be, as shown in error messages
+        match self {
+            ParameterType::Int => "an integer".to_owned(),
+            ParameterType::Text => "text".to_owned(),
+            ParameterType::Enum(names) => format!("one of {}", names.join("|")),
+            ParameterType::Statistic(names) if names.iter().any(|n| n == "all") => "a statistic name or all".to_owned(),
+            ParameterType::Statistic(_) => "a statistic name".to_owned(),
+            ParameterType::WeightedStatistic(_) => "a statistic name, optionally followed by :<weight>".to_owned(),
+        }
+    }
+
+    fn accepts(&self, value: &str) -> bool {
+        let is_one_of = |names: &[String], v: &str| names.iter().any(|n| n == v.trim());
+        match self {
+            ParameterType::Int => value.trim().parse::<i64>().is_ok(),
+            ParameterType::Text => true,
+            ParameterType::Enum(names) | ParameterType::Statistic(names) => is_one_of(names, value),
+            ParameterType::WeightedStatistic(names) => match value.split_once(':') {
+                Some((statistic, weight)) => is_one_of(names, statistic) && weight.trim().parse::<f64>().is_ok(),
+                None => is_one_of(names, value),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: String,
+    pub kind: ParameterType,
+    pub required: bool,
+    // Whether the parameter takes a comma-separated list of values
+    pub multi: bool,
+    // Filled in by the server when the parameter is omitted
+    pub default: Option<String>,
+    pub description: String,
+}
+impl Parameter {
+    fn required(name: &str, kind: ParameterType, description: &str) -> Self {
+        Parameter {
+            name: name.to_owned(),
+            kind,
+            required: true,
+            multi: false,
+            default: None,
+            description: description.to_owned(),
+        }
+    }
+
+    fn optional(name: &str, kind: ParameterType, description: &str) -> Self {
+        Parameter {
+            required: false,
+            ..Parameter::required(name, kind, description)
+        }
+    }
+
+    fn multi(self) -> Self {
+        Parameter { multi: true, ..self }
+    }
+
+    fn default(self, value: &str) -> Self {
+        Parameter {
+            default: Some(value.to_owned()),
+            ..self
+        }
+    }
+
+    fn check(&self, endpoint: &str, values: &mut Vec<String>, allow_placeholders: bool) -> Result<(), ProtocolError> {
+        // Normalizes values in place: single free text values are joined back together, as commas in them are part of
+        // the text, and blank values are dropped from lists
+        if let (ParameterType::Text, false) = (&self.kind, self.multi) {
+            *values = vec![values.join(&LIST_SEPARATOR.to_string())];
+        }
+        if self.multi {
+            values.retain(|v| !v.trim().is_empty());
+        }
+        if self.required && values.is_empty() {
+            return Err(ProtocolError::MissingParameter {
+                endpoint: endpoint.to_owned(),
+                parameter: self.name.clone(),
+            });
+        }
+        if !self.multi && values.len() > 1 {
+            return Err(ProtocolError::TooManyValues {
+                endpoint: endpoint.to_owned(),
+                parameter: self.name.clone(),
+            });
+        }
+        // Placeholder values of saved queries are checked once they are filled in, when the query is run
+        let is_placeholder = |v: &str| allow_placeholders && !placeholders(v).is_empty();
+        let invalid = values.iter().find(|v| !is_placeholder(v) && !self.kind.accepts(v));
+        match invalid {
+            Some(value) => Err(ProtocolError::InvalidParameterValue {
+                endpoint: endpoint.to_owned(),
+                parameter: self.name.clone(),
+                value: value.to_owned(),
+                expected: self.kind.describe(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+// Methods the server implements; every endpoint is read with GET (HEAD returns the same headers without a body)
+pub const ALLOWED_METHODS: [&str; 2] = ["GET", "HEAD"];
 
 #[derive(std::fmt::Debug, Clone)]
 pub struct Endpoint {
     pub uri: String,
     pub query_pv_map: QueryPVMap,
+    pub headers: HeaderMap,
+    pub method: String,
+    pub version: String,
+    // API version the request is sent or served as, e.g. v1 (the path's version segment)
+    pub api_version: String,
+    // Schema of the query parameters, and what the endpoint does; only set on AUTHORITATIVE_ENDPOINTS
+    pub parameters: Vec<Parameter>,
+    pub description: String,
 }
 impl Endpoint {
     pub fn new<T: ToString>(uri: T, query_pv_map: QueryPVMap) -> Self {
-        let uri = uri.to_string().replace('+', " ");
-        Self { uri, query_pv_map }
+        Self {
+            uri: uri.to_string(),
+            query_pv_map,
+            headers: HashMap::new(),
+            method: "GET".to_owned(),
+            version: "HTTP/1.1".to_owned(),
+            api_version: CURRENT_VERSION.to_owned(),
+            parameters: vec![],
+            description: String::new(),
+        }
     }
-    fn new_authority<T: ToString>(uri: T, query_parameters: &[T]) -> Self {
+    fn new_authority<T: ToString>(uri: T, description: &str, parameters: Vec<Parameter>) -> Self {
         // Creates a new AUTHORITATIVE ENDPOINT:
         //      * All Endpoints created with Endpoint::new() MUST ONLY include endpoint uri's and their respective query parameters as defined
         //          by AUTHORITATIVE ENDPOINTS
         //
         // Note that for an Endpoint to be considered an AUTHORITY, it MUST exist in the ENDPOINTS const, otherwise it is just another Endpoint...
         let mut fauxmap: QueryPVMap = HashMap::new();
-        for p in query_parameters {
-            fauxmap.insert(p.to_string(), vec![]);
+        for p in &parameters {
+            fauxmap.insert(p.name.clone(), vec![]);
         }
         Self {
             uri: uri.to_string(),
             query_pv_map: fauxmap,
+            headers: HashMap::new(),
+            method: "GET".to_owned(),
+            version: "HTTP/1.1".to_owned(),
+            api_version: CURRENT_VERSION.to_owned(),
+            parameters,
+            description: description.to_owned(),
+        }
+    }
+    pub fn validated(&self) -> Result<Endpoint, ProtocolError> {
+        // Checks the request against its endpoint's schema, returning it with defaults filled in and values normalized
+        let authority =
+            clone_authoritative_endpoint_by_uri(&self.uri).ok_or(ProtocolError::UnknownEndpoint(self.uri.clone()))?;
+        self.validate(&authority, false)
+    }
+    pub fn validate(&self, authority: &Endpoint, allow_placeholders: bool) -> Result<Endpoint, ProtocolError> {
+        // As validated, against the given authority (e.g. one the client received from the endpoints endpoint)
+        let mut request = self.clone();
+        if !OPEN_URIS.contains(&self.uri.as_str()) {
+            let unknown = request.query_pv_map.keys().find(|p| {
+                !GLOBAL_PARAMETERS.contains(&p.as_str()) && !authority.parameters.iter().any(|s| &s.name == *p)
+            });
+            if let Some(parameter) = unknown {
+                return Err(ProtocolError::UnknownParameter {
+                    endpoint: self.uri.clone(),
+                    parameter: parameter.to_owned(),
+                });
+            }
+        }
+        for parameter in &authority.parameters {
+            match request.query_pv_map.get_mut(&parameter.name) {
+                Some(values) => parameter.check(&self.uri, values, allow_placeholders)?,
+                None if parameter.required => {
+                    return Err(ProtocolError::MissingParameter {
+                        endpoint: self.uri.clone(),
+                        parameter: parameter.name.clone(),
+                    })
+                }
+                None => {
+                    if let Some(default) = &parameter.default {
+                        request
+                            .query_pv_map
+                            .insert(parameter.name.clone(), vec![default.clone()]);
+                    }
+                }
+            }
+        }
+        Ok(request)
+    }
+    pub fn get_path(&self) -> String {
+        // Requests sent as the unversioned version keep the paths servers predating versioning understand
+        match self.api_version.as_str() {
+            version::UNVERSIONED => format!("/{}", percent_encode(&self.uri)),
+            api_version => format!("/{}/{}", api_version, percent_encode(&self.uri)),
         }
     }
     pub fn get_valued_uri(&self) -> Result<String, &str> {
         // If no query parameters have associated values, then they are useless, so return base uri
         if self.query_pv_map.values().len() == 0 {
-            return Ok(format!("/{}", self.uri));
+            return Ok(self.get_path());
         }
         // println!("get_valued_uri query_pv_map: {:?}", self.query_pv_map);
-        let mut formatted = format!("/{}?", &self.uri);
+        let mut formatted = format!("{}?", self.get_path());
         for (p, vset) in self.query_pv_map.iter() {
             // println!("VSET: {:?}", vset);
             if !vset.is_empty() {
-                let vals = vset.join(",");
+                // Values are encoded one by one, so that separators within them are encoded too
+                let vals = vset
+                    .iter()
+                    .map(|v| percent_encode(v))
+                    .collect::<Vec<String>>()
+                    .join(&LIST_SEPARATOR.to_string());
                 // println!("Values: {}", vals);
-                formatted.push_str(format!("{p}={vals}&").as_str());
+                formatted.push_str(format!("{}={vals}&", percent_encode(p)).as_str());
             }
         }
         formatted.pop().unwrap();
-        // Replace spaces with pluses and eliminate quotes
-        formatted = formatted.replace(' ', "+").replace('\"', "");
         Ok(formatted)
     }
-    pub fn get_request_string(&self) -> String {
+    pub fn get_request_string(&self, host: &str) -> String {
+        // Any headers set on the endpoint (e.g. a request id) are sent after the standard ones
         let uri = self.get_valued_uri().unwrap();
-        format!(
-            "GET {} HTTP/1.1\nUser-Agent: crabbysoccer/1.0.0\nHost: temporarily-not-included\nAccept-Language: en{}",
-            uri,
-            char::from(REQUEST_TERMINATOR)
-        )
-    }
-}
-impl From<Vec<String>> for Endpoint {
-    fn from(value: Vec<String>) -> Self {
-        // value is expected to be an HTTP GET request
-        let uri = value[0].split(' ').collect::<Vec<&str>>()[1].to_owned();
-        let user_agent = value[1].split(' ').collect::<Vec<&str>>()[1].to_owned();
-        println!("Parsed:\n\turi={}\n\tuser-agent={}", uri, user_agent);
-        let uri_split: (&str, &str) = uri.split_once('?').unwrap();
-        let query_params: Vec<&str> = uri_split.1.split('&').collect();
-        let endpoint: String = String::from(&uri_split.0[1..]);
+        let mut request = format!(
+            "GET {uri} HTTP/1.1{CRLF}Host: {host}{CRLF}User-Agent: crabbysoccer/1.0.0{CRLF}Accept-Language: en{CRLF}"
+        );
+        let mut headers: Vec<(&String, &String)> = self.headers.iter().collect();
+        headers.sort();
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}{CRLF}", name, value));
+        }
+        request.push_str(CRLF);
+        request
+    }
+    pub fn keep_alive(&self) -> bool {
+        // HTTP/1.1 connections persist unless either side sends "Connection: close"; HTTP/1.0 ones must opt in
+        let connection = self.headers.get("connection").map(|c| c.to_lowercase());
+        match self.version.as_str() {
+            "HTTP/1.0" => connection.is_some_and(|c| c.contains("keep-alive")),
+            _ => !connection.is_some_and(|c| c.contains("close")),
+        }
+    }
+}
+impl TryFrom<Vec<String>> for Endpoint {
+    type Error = ProtocolError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        // value is expected to be the head of an HTTP/1.x request: the request line followed by header lines
+        let request_line = value.first().ok_or(ProtocolError::EmptyRequest)?;
+        let (method, target, version) = match request_line.split(' ').collect::<Vec<&str>>()[..] {
+            [method, target, version] if target.starts_with('/') && version.starts_with("HTTP/") => {
+                (method, target, version)
+            }
+            _ => return Err(ProtocolError::MalformedRequestLine(request_line.to_owned())),
+        };
+        if version != "HTTP/1.1" && version != "HTTP/1.0" {
+            return Err(ProtocolError::UnsupportedVersion(version.to_owned()));
+        }
+        let (path, query_param_str) = target[1..].split_once('?').unwrap_or((&target[1..], ""));
+        let (api_version, uri) = version::split_path(path);
+        let api_version = api_version.unwrap_or(version::UNVERSIONED);
+        // The handshake must be reachable whichever versions the client and server speak
+        if version::status(api_version).is_none() && uri != version::HANDSHAKE_URI {
+            return Err(ProtocolError::UnsupportedApiVersion(api_version.to_owned()));
+        }
         let mut query_pv_map: QueryPVMap = HashMap::new();
-        for qp_entry in query_params {
-            let (p, vals) = qp_entry.split_once('=').unwrap();
-            let vals: Vec<String> = vals.split(',').map(String::from).collect();
-            query_pv_map.insert(p.to_owned(), vals);
+        for qp_str in query_param_str.split('&').filter(|qp| !qp.is_empty()) {
+            let (qp, qvals) = qp_str
+                .split_once('=')
+                .ok_or(ProtocolError::MalformedQueryParameter(qp_str.to_owned()))?;
+            // Values are split before they are decoded, so an encoded separator stays part of its value
+            let qvals = qvals
+                .split(LIST_SEPARATOR)
+                .map(percent_decode)
+                .collect::<Result<Vec<String>, ProtocolError>>()?;
+            query_pv_map.insert(percent_decode(qp)?, qvals);
+        }
+        let mut endpoint = Endpoint::new(percent_decode(uri)?, query_pv_map);
+        endpoint.method = method.to_owned();
+        endpoint.version = version.to_owned();
+        endpoint.api_version = api_version.to_owned();
+        // Header names are case-insensitive, so they are stored lowercased
+        for header in &value[1..] {
+            let (name, value) = header
+                .split_once(':')
+                .ok_or(ProtocolError::MalformedHeader(header.to_owned()))?;
+            endpoint
+                .headers
+                .insert(name.trim().to_lowercase(), value.trim().to_owned());
         }
-        Endpoint::new(endpoint, query_pv_map)
+        if !ALLOWED_METHODS.contains(&method) {
+            return Err(ProtocolError::MethodNotAllowed(method.to_owned()));
+        }
+        // RFC 9112: a server must reject HTTP/1.1 requests without a Host header
+        if version == "HTTP/1.1" && !endpoint.headers.contains_key("host") {
+            return Err(ProtocolError::MissingHostHeader);
+        }
+        Ok(endpoint)
     }
 }
 
+#[derive(Debug)]
+pub enum ProtocolError {
+    EmptyRequest,
+    MalformedRequestLine(String),
+    MalformedHeader(String),
+    MissingHostHeader,
+    MethodNotAllowed(String),
+    UnsupportedVersion(String),
+    // An API version the server does not (or no longer) serve, or a handshake offering none it does
+    UnsupportedApiVersion(String),
+    // A query parameter without a value, e.g. "player_id" rather than "player_id=12345"
+    MalformedQueryParameter(String),
+    // A '%' not followed by two hex digits, or an encoding of bytes that are not UTF-8
+    InvalidPercentEncoding(String),
+    UnknownEndpoint(String),
+    MissingParameter {
+        endpoint: String,
+        parameter: String,
+    },
+    UnknownParameter {
+        endpoint: String,
+        parameter: String,
+    },
+    // More than one value for a parameter that takes a single one
+    TooManyValues {
+        endpoint: String,
+        parameter: String,
+    },
+    InvalidParameterValue {
+        endpoint: String,
+        parameter: String,
+        value: String,
+        expected: String,
+    },
+    UnsupportedFormat(String),
+    // A query that cannot be saved, e.g. one that runs another saved query
+    InvalidSavedQuery(String),
+    // explain=true on an endpoint in WRITE_URIS, or on a run-query whose saved query is one
+    UnexplainableEndpoint(String),
+}
+impl ProtocolError {
+    pub fn kind(&self) -> &'static str {
+        // Stable identifier sent to clients alongside the message
+        match self {
+            ProtocolError::EmptyRequest
+            | ProtocolError::MalformedRequestLine(_)
+            | ProtocolError::MalformedHeader(_)
+            | ProtocolError::MissingHostHeader
+            | ProtocolError::MalformedQueryParameter(_)
+            | ProtocolError::InvalidPercentEncoding(_) => "malformed-request",
+            ProtocolError::MethodNotAllowed(_) => "method-not-allowed",
+            ProtocolError::UnsupportedVersion(_) => "unsupported-version",
+            ProtocolError::UnsupportedApiVersion(_) => "unsupported-api-version",
+            ProtocolError::UnknownEndpoint(_) => "unknown-endpoint",
+            ProtocolError::MissingParameter { .. } => "missing-parameter",
+            ProtocolError::UnknownParameter { .. } => "unknown-parameter",
+            ProtocolError::TooManyValues { .. }
+            | ProtocolError::InvalidParameterValue { .. }
+            | ProtocolError::UnexplainableEndpoint(_) => "invalid-parameter",
+            ProtocolError::UnsupportedFormat(_) => "unsupported-format",
+            ProtocolError::InvalidSavedQuery(_) => "invalid-saved-query",
+        }
+    }
+}
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProtocolError::EmptyRequest => write!(f, "Empty request"),
+            ProtocolError::MalformedRequestLine(line) => write!(f, "Malformed request line: {}", line),
+            ProtocolError::MalformedHeader(header) => write!(f, "Malformed header: {}", header),
+            ProtocolError::MissingHostHeader => write!(f, "HTTP/1.1 requests require a Host header"),
+            ProtocolError::MethodNotAllowed(method) => write!(f, "Method not allowed: {}", method),
+            ProtocolError::UnsupportedVersion(version) => write!(f, "Unsupported HTTP version: {}", version),
+            ProtocolError::UnsupportedApiVersion(version) => write!(
+                f,
+                "Unsupported API version: {} (supported: {})",
+                version,
+                version::version_names().join(", ")
+            ),
+            ProtocolError::MalformedQueryParameter(qp) => write!(f, "Malformed query parameter: {}", qp),
+            ProtocolError::InvalidPercentEncoding(s) => write!(f, "Invalid percent-encoding: {}", s),
+            ProtocolError::UnknownEndpoint(uri) => write!(f, "No such endpoint: /{}", uri),
+            ProtocolError::MissingParameter { endpoint, parameter } => {
+                write!(f, "{} requires the {} parameter", endpoint, parameter)
+            }
+            ProtocolError::UnknownParameter { endpoint, parameter } => {
+                write!(f, "{} has no {} parameter", endpoint, parameter)
+            }
+            ProtocolError::TooManyValues { endpoint, parameter } => {
+                write!(f, "{} takes a single {} value", endpoint, parameter)
+            }
+            ProtocolError::InvalidParameterValue {
+                endpoint,
+                parameter,
+                value,
+                expected,
+            } => write!(
+                f,
+                "Invalid {} for {}: {} (expected {})",
+                parameter, endpoint, value, expected
+            ),
+            ProtocolError::UnsupportedFormat(format) => write!(f, "Unsupported format: {}", format),
+            ProtocolError::InvalidSavedQuery(message) => write!(f, "Invalid saved query: {}", message),
+            ProtocolError::UnexplainableEndpoint(uri) => {
+                write!(f, "explain is not supported by {}, as it writes to the database", uri)
+            }
+        }
+    }
+}
+impl std::error::Error for ProtocolError {}
+
+fn owned(names: Vec<&str>) -> Vec<String> {
+    names.into_iter().map(String::from).collect()
+}
+
 #[allow(non_snake_case)]
-pub fn AUTHORITATIVE_ENDPOINTS() -> [Endpoint; 2] {
+pub fn AUTHORITATIVE_ENDPOINTS() -> [Endpoint; 20] {
+    use Parameter as P;
+    use ParameterType::*;
+    let position = || Enum(owned(database::position_names()));
+    let statistic = || Statistic(owned(database::STATISTICS_COLUMNS.to_vec()));
+    let statistic_or_all = || {
+        let mut names = owned(database::STATISTICS_COLUMNS.to_vec());
+        names.push("all".to_owned());
+        Statistic(names)
+    };
+    let position_filter = "Only players playing this position or sub-position";
+    let club_filter = "Only players of this club";
     [
-        Endpoint::new_authority("get-player", &["player_id", "statistics"]),
-        Endpoint::new_authority("get-all-players", &["name"]),
+        Endpoint::new_authority(
+            "get-player",
+            "A player's positions and statistics, or those of every player",
+            vec![
+                P::optional("player_id", Int, "The player; every player if omitted"),
+                P::optional(
+                    "statistics",
+                    statistic_or_all(),
+                    "Statistics to show; with all, those irrelevant to a player's positions are hidden for that player",
+                )
+                .multi(),
+                P::optional("position", position(), position_filter),
+            ],
+        ),
+        Endpoint::new_authority(
+            "get-all-players",
+            "Players' details, optionally searched by name",
+            vec![
+                P::optional("name", Text, "Part of the player's name"),
+                P::optional("position", position(), position_filter),
+            ],
+        ),
+        Endpoint::new_authority(
+            "best-xi",
+            "The highest scoring eleven for a formation",
+            vec![
+                P::required("formation", Text, "Outfield players per line, e.g. 4-3-3"),
+                P::optional("club", Text, club_filter),
+                P::required(
+                    "score",
+                    WeightedStatistic(owned(database::STATISTICS_COLUMNS.to_vec())),
+                    "Statistics summed into each player's score, each weighted by its :<weight> (default 1)",
+                )
+                .multi(),
+            ],
+        ),
+        Endpoint::new_authority(
+            "fantasy-points",
+            "Players ranked by fantasy points under a scoring ruleset",
+            vec![
+                P::optional("ruleset", Text, "Name of a ruleset in the rulesets directory").default("default"),
+                P::optional("club", Text, club_filter),
+                P::optional("position", position(), position_filter),
+                P::optional("limit", Int, "Number of players to return"),
+            ],
+        ),
+        Endpoint::new_authority(
+            "validate",
+            "Rows breaking the data's consistency rules",
+            vec![P::optional(
+                "rule",
+                Enum(owned(database::validation_rule_names())),
+                "Rules to check; every rule if omitted",
+            )
+            .multi()],
+        ),
+        Endpoint::new_authority(
+            "set-sub-positions",
+            "Replaces a player's sub-positions",
+            vec![
+                P::required("player_id", Int, "The player"),
+                P::optional(
+                    "sub_positions",
+                    Enum(owned(database::sub_position_names())),
+                    "The player's sub-positions; empty to clear them",
+                )
+                .multi(),
+            ],
+        ),
+        Endpoint::new_authority(
+            "aggregate",
+            "Statistics aggregated over groups of players",
+            vec![
+                P::required(
+                    "group_by",
+                    Enum(owned(database::group_by_names())),
+                    "How players are grouped",
+                ),
+                P::required("stats", statistic(), "Statistics to aggregate").multi(),
+                P::optional("fn", Enum(owned(database::aggregate_fn_names())), "Aggregate function").default("sum"),
+            ],
+        ),
+        Endpoint::new_authority(
+            "distribution",
+            "Histogram and summary of a statistic",
+            vec![
+                P::required("statistic", statistic(), "The statistic"),
+                P::optional("position", position(), position_filter),
+                P::optional("club", Text, club_filter),
+                P::optional("bins", Int, "Number of histogram bins"),
+            ],
+        ),
+        Endpoint::new_authority(
+            "correlations",
+            "Correlations between statistics",
+            vec![
+                P::required("statistics", statistic_or_all(), "Statistics to correlate").multi(),
+                P::optional(
+                    "method",
+                    Enum(owned(database::correlation_method_names())),
+                    "Correlation coefficient",
+                )
+                .default("pearson"),
+                P::optional("position", position(), position_filter),
+                P::optional("club", Text, club_filter),
+                P::optional(
+                    "view",
+                    Enum(owned(database::correlation_view_names())),
+                    "Full matrix, or the strongest pairs",
+                )
+                .default("matrix"),
+                P::optional("top", Int, "Number of pairs shown by view=pairs"),
+            ],
+        ),
+        Endpoint::new_authority(
+            "create-watchlist",
+            "Creates an empty watchlist",
+            vec![
+                P::required("user", Text, "Owner of the watchlist"),
+                P::required("list", Text, "Name of the watchlist"),
+            ],
+        ),
+        Endpoint::new_authority(
+            "get-watchlists",
+            "A user's watchlists",
+            vec![P::required("user", Text, "Owner of the watchlists")],
+        ),
+        Endpoint::new_authority(
+            "get-watchlist",
+            "The players on a watchlist, with the user's notes on them",
+            vec![
+                P::required("user", Text, "Owner of the watchlist"),
+                P::required("list", Text, "Name of the watchlist"),
+                P::optional("statistics", statistic_or_all(), "Statistics to show, as in get-player").multi(),
+            ],
+        ),
+        Endpoint::new_authority(
+            "add-to-watchlist",
+            "Adds players to a watchlist",
+            vec![
+                P::required("user", Text, "Owner of the watchlist"),
+                P::required("list", Text, "Name of the watchlist"),
+                P::required("player_id", Int, "Players to add").multi(),
+            ],
+        ),
+        Endpoint::new_authority(
+            "remove-from-watchlist",
+            "Removes players from a watchlist",
+            vec![
+                P::required("user", Text, "Owner of the watchlist"),
+                P::required("list", Text, "Name of the watchlist"),
+                P::required("player_id", Int, "Players to remove").multi(),
+            ],
+        ),
+        Endpoint::new_authority(
+            "set-note",
+            "Replaces a user's note and tags on a player; omitting both removes them",
+            vec![
+                P::required("user", Text, "Author of the note"),
+                P::required("player_id", Int, "The player"),
+                P::optional("note", Text, "Free text"),
+                P::optional("tags", Text, "Tags").multi(),
+            ],
+        ),
+        Endpoint::new_authority(
+            "save-query",
+            "Saves a request, written as in the client, to be run with run-query",
+            vec![
+                P::required("name", Text, "Name to save the query under"),
+                P::required("query", Text, "The request, which may contain {parameter} placeholders"),
+            ],
+        ),
+        // Also accepts a parameter per placeholder of the saved query
+        Endpoint::new_authority(
+            "run-query",
+            "Runs a saved query; each of its placeholders is given as a parameter",
+            vec![P::required("name", Text, "Name of the saved query")],
+        ),
+        Endpoint::new_authority("get-saved-queries", "Every saved query", vec![]),
+        Endpoint::new_authority("endpoints", "Every endpoint and its parameters", vec![]),
+        Endpoint::new_authority(
+            version::HANDSHAKE_URI,
+            "Agrees on the API version to use: the newest one offered that the server serves",
+            vec![P::required("versions", Text, "API versions the client speaks, e.g. v1").multi()],
+        ),
     ]
 }
 
+// Column names of the endpoints endpoint's response: a row per parameter, or a single row with no parameter for
+// endpoints without any. Booleans are 1 or 0
+pub const ENDPOINTS_COLUMNS: [&str; 9] = [
+    "endpoint",
+    "description",
+    "parameter",
+    "type",
+    "required",
+    "multi",
+    "default",
+    "allowed_values",
+    "parameter_description",
+];
+
+// Separates the allowed values of a parameter in the endpoints endpoint's response
+const ALLOWED_VALUES_SEPARATOR: char = '|';
+
+pub fn endpoints_table() -> ResultTable {
+    let text = |s: &str| Value::Text(s.to_owned());
+    let mut rows: Vec<Vec<Value>> = vec![];
+    for endpoint in AUTHORITATIVE_ENDPOINTS() {
+        if endpoint.parameters.is_empty() {
+            let mut row = vec![text(&endpoint.uri), text(&endpoint.description)];
+            row.resize(ENDPOINTS_COLUMNS.len(), Value::Null);
+            rows.push(row);
+        }
+        for p in &endpoint.parameters {
+            let allowed_values = p.kind.allowed_values();
+            rows.push(vec![
+                text(&endpoint.uri),
+                text(&endpoint.description),
+                text(&p.name),
+                text(p.kind.name()),
+                Value::Integer(p.required as i64),
+                Value::Integer(p.multi as i64),
+                p.default.clone().map_or(Value::Null, Value::Text),
+                match allowed_values.is_empty() {
+                    true => Value::Null,
+                    false => Value::Text(allowed_values.join(&ALLOWED_VALUES_SEPARATOR.to_string())),
+                },
+                text(&p.description),
+            ]);
+        }
+    }
+    ResultTable::new(&ENDPOINTS_COLUMNS, rows)
+}
+
+pub fn endpoints_from_text(response: &str) -> Option<Vec<Endpoint>> {
+    // Inverse of endpoints_table, from its text rendering; None if response is not one
+    let mut lines = response.lines();
+    if !lines.next()?.split(TEXT_SEPARATOR).eq(ENDPOINTS_COLUMNS) {
+        return None;
+    }
+    let mut endpoints: Vec<Endpoint> = vec![];
+    for line in lines {
+        let cells: Vec<String> = line.split(TEXT_SEPARATOR).map(unescape_text).collect();
+        if cells.len() != ENDPOINTS_COLUMNS.len() {
+            return None;
+        }
+        let cell = |i: usize| Some(cells[i].clone()).filter(|c| c != MISSING_VALUE);
+        if endpoints.last().is_none_or(|e| e.uri != cells[0]) {
+            endpoints.push(Endpoint::new_authority(&cells[0], &cells[1], vec![]));
+        }
+        let Some(name) = cell(2) else { continue };
+        let allowed_values = cell(7).map_or(vec![], |v| {
+            v.split(ALLOWED_VALUES_SEPARATOR).map(String::from).collect()
+        });
+        let endpoint = endpoints.last_mut().unwrap();
+        endpoint.query_pv_map.insert(name.clone(), vec![]);
+        endpoint.parameters.push(Parameter {
+            name,
+            kind: ParameterType::from_name(&cells[3], allowed_values)?,
+            required: cells[4] == "1",
+            multi: cells[5] == "1",
+            default: cell(6),
+            description: cells[8].clone(),
+        });
+    }
+    Some(endpoints)
+}
+
+// Format: (endpoint uri, parameter that a bare client argument is taken as), e.g. "run-query young-strikers"
+pub const POSITIONAL_PARAMETERS: [(&str, &str); 1] = [("run-query", "name")];
+
+// Endpoints that cannot be saved, as running them from a saved query could recurse
+const UNSAVABLE_URIS: [&str; 2] = ["save-query", "run-query"];
+
+// Parameter of run-query naming the saved query, so it cannot be used as a placeholder
+const SAVED_QUERY_NAME_PARAMETER: &str = "name";
+
+fn placeholders(query: &str) -> Vec<&str> {
+    // Placeholders are written {parameter}, e.g. "get-player position={position}"
+    query
+        .split('{')
+        .skip(1)
+        .filter_map(|s| s.split_once('}').map(|(p, _)| p))
+        .collect()
+}
+
+pub fn parse_saved_query(query: &str) -> Result<Endpoint, ProtocolError> {
+    // Parses a query in client CLI syntax (<COMMAND> <PARAMETER>=<VALUE_1>,<VALUE_2> ...), checking the endpoint and
+    // its parameters against AUTHORITATIVE_ENDPOINTS; placeholders are left in the values, for fill_placeholders
+    // format and explain are not accepted: they apply to the run-query request's response, so are given to run-query
+    let args = split_args(query);
+    let (uri, args) = args
+        .split_first()
+        .ok_or(ProtocolError::InvalidSavedQuery("query is blank".to_owned()))?;
+    let uri = uri.as_str();
+    if UNSAVABLE_URIS.contains(&uri) {
+        return Err(ProtocolError::InvalidSavedQuery(format!("{} cannot be saved", uri)));
+    }
+    if placeholders(query).contains(&SAVED_QUERY_NAME_PARAMETER) {
+        return Err(ProtocolError::InvalidSavedQuery(format!(
+            "{{{}}} is reserved for the saved query's name",
+            SAVED_QUERY_NAME_PARAMETER
+        )));
+    }
+    let authority = clone_authoritative_endpoint_by_uri(uri).ok_or(ProtocolError::UnknownEndpoint(uri.to_owned()))?;
+    let mut query_pv_map: QueryPVMap = HashMap::new();
+    for arg in args {
+        let (p, vals) = arg
+            .split_once('=')
+            .ok_or(ProtocolError::MalformedQueryParameter(arg.to_owned()))?;
+        if !authority.query_pv_map.contains_key(p) {
+            return Err(ProtocolError::UnknownParameter {
+                endpoint: uri.to_owned(),
+                parameter: p.to_owned(),
+            });
+        }
+        query_pv_map.insert(p.to_owned(), parse_values(vals));
+    }
+    // Values are type-checked now where they can be, so that mistakes surface when the query is saved
+    Endpoint::new(uri, query_pv_map).validate(&authority, true)
+}
+
+pub fn fill_placeholders(saved: &Endpoint, run_request: &Endpoint) -> Result<Endpoint, ProtocolError> {
+    // Substitutes each {parameter} in the values of a parsed saved query with the values of that parameter of the
+    // run-query request. Substituted values are never re-parsed, so they cannot add parameters or split on spaces
+    // A value that is just a placeholder takes each of the parameter's values; within other text they are joined
+    let run_values = |placeholder: &str| {
+        run_request
+            .query_pv_map
+            .get(placeholder)
+            .cloned()
+            .ok_or(ProtocolError::MissingParameter {
+                endpoint: run_request.uri.clone(),
+                parameter: placeholder.to_owned(),
+            })
+    };
+    let mut filled = saved.clone();
+    for values in filled.query_pv_map.values_mut() {
+        let mut filled_values: Vec<String> = vec![];
+        for value in values.iter() {
+            match placeholders(value)[..] {
+                [placeholder] if *value == format!("{{{}}}", placeholder) => {
+                    filled_values.extend(run_values(placeholder)?)
+                }
+                ref value_placeholders => {
+                    let mut filled_value = value.to_owned();
+                    for placeholder in value_placeholders {
+                        let joined = run_values(placeholder)?.join(&LIST_SEPARATOR.to_string());
+                        filled_value = filled_value.replace(&format!("{{{}}}", placeholder), &joined);
+                    }
+                    filled_values.push(filled_value);
+                }
+            }
+        }
+        *values = filled_values;
+    }
+    Ok(filled)
+}
+
 pub fn clone_authoritative_endpoint_by_uri(uri: &str) -> Option<Endpoint> {
     let ep = AUTHORITATIVE_ENDPOINTS()
         .into_iter()
@@ -101,3 +998,42 @@ pub fn clone_authoritative_endpoint_by_uri(uri: &str) -> Option<Endpoint> {
         Some(ep[0].clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_encoding_round_trips() {
+        for s in [
+            "plain",
+            "Bukayo Saka",
+            "a,b&c=d?e#f",
+            "100%",
+            "1+1",
+            "Ødegaard",
+            "%2C",
+            "",
+        ] {
+            assert_eq!(percent_decode(&percent_encode(s)).unwrap(), s);
+        }
+        assert_eq!(percent_encode("a b,c"), "a%20b%2Cc");
+        assert_eq!(percent_decode("a%2cb+c").unwrap(), "a,b+c");
+    }
+
+    #[test]
+    fn percent_decode_rejects_invalid_escapes() {
+        for s in ["%", "%4", "%+F", "%-1", "% 1", "%zz", "%FF"] {
+            assert!(percent_decode(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn split_list_unescapes_separators() {
+        assert_eq!(split_list("goals,assists"), ["goals", "assists"]);
+        assert_eq!(split_list("strikers\\,u23,wingers"), ["strikers,u23", "wingers"]);
+        assert_eq!(split_list("a\\b"), ["a\\b"]);
+        assert_eq!(split_list(""), [""]);
+        assert_eq!(parse_values("\"strikers, u23\""), ["strikers, u23"]);
+    }
+}
@@ -1,12 +1,19 @@
 use crate::{
+    cache::{QueryCache, QUERY_CACHE},
     common::{self, println_then_show_input_indicator, InputAction},
-    database,
-    requests::{self, Endpoint, QueryPVMap},
+    database::{
+        self,
+        format::{OutputFormat, ResultTable},
+        Backend, DbError, DuplicatePolicy, MemoryStore, PlayerStore,
+    },
+    http::{self, HeadError, Response},
+    requests::{self, Endpoint, ProtocolError},
+    version::{self, VersionStatus},
 };
+use rusqlite::types::Value;
 use std::{
     any::Any,
-    collections::HashMap,
-    io::{self, BufRead, BufReader, ErrorKind, Write},
+    io::{self, BufReader, ErrorKind, Read, Write},
     net::{TcpListener, TcpStream},
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -16,6 +23,8 @@ use std::{
     time::Duration,
 };
 
+const SERVER_ADDR: &str = "127.0.0.1:7878";
+
 const _HELP_MSG: &str = "
  ----------------------------------------------------------------------
 | CrabbySoccer Server CLI                                              |
@@ -26,6 +35,12 @@ const _HELP_MSG: &str = "
         quit, q - shutdown server
         help, h - print help
         list-connections, lc - list clients
+        validate, v - run data quality checks over the imported statistics
+        backup, bk <PATH> - copy the database to <PATH> (safe while clients are connected)
+        vacuum, vac - rebuild the database file, reclaiming unused space
+        analyze, an - gather query planner statistics
+        integrity-check, ic - check the database for corruption
+        cache-stats, cs - print query cache hit/miss counters
 ";
 
 fn print_help() {
@@ -42,14 +57,17 @@ struct Connection {
 impl Connection {
     fn new(stream: TcpStream, shutdown_trigger: Option<Arc<AtomicBool>>) -> Self {
         Connection {
-            name: stream.peer_addr().unwrap().to_string().to_owned(),
+            // The peer may already have disconnected, in which case its address is unavailable
+            name: stream
+                .peer_addr()
+                .map_or("[UNKNOWN PEER]".to_owned(), |addr| addr.to_string()),
             stream: Some(stream),
             _shutdown_trigger: shutdown_trigger.unwrap_or(Arc::new(AtomicBool::new(false))),
             _handle: None,
         }
     }
 
-    fn start_thread(self) -> Self {
+    fn start_thread(self, backend: Backend) -> Self {
         // Consumes self and returns corpse with stream inaccessible
         let sd = self._shutdown_trigger.clone();
         let name = self.name.clone();
@@ -59,49 +77,74 @@ impl Connection {
                 .unwrap()
                 .set_nonblocking(false)
                 .expect("set_nonblocking call failed");
-            let db = database::DB::new();
-            let mut buf_reader = BufReader::new(self.stream.as_ref().unwrap());
-            let mut buf: Vec<u8> = vec![];
+            let mut stream = self.stream.as_ref().unwrap();
+            let db = match backend.open() {
+                Ok(db) => db,
+                Err(e) => {
+                    // Without a store no request can be served, so report why and drop the client
+                    let response = get_error_response(&e.into());
+                    println!("Dropping connection {}: {}", self.name, response.body);
+                    let _ = stream.write_all(response.to_http(false, true).as_bytes());
+                    return;
+                }
+            };
+            let mut buf_reader = BufReader::new(stream);
             loop {
                 if self._shutdown_trigger.load(Ordering::Relaxed) {
                     println!("Dropping connection: {}", self.name);
-                    self.stream.as_ref().unwrap().write_all(&[]).unwrap(); // Send 0-len to notify other end of drop
+                    let _ = stream.write_all(&[]); // Send 0-len to notify other end of drop
                     break;
                 }
-                buf.clear();
                 // Below should be converted to a non-blocking read in order to avoid hanging on server quit
-                match buf_reader.read_until(requests::REQUEST_TERMINATOR, &mut buf) {
-                    Ok(0) => {
+                let head = match http::read_head(&mut buf_reader) {
+                    Ok(Some(head)) => head,
+                    Ok(None) => {
                         println!("Connection {} dropped!", self.name);
                         break;
                     }
-                    Ok(_) => (),
-                    Err(_) => (),
-                }
-                let http_request: Vec<String> = buf
-                    .lines()
-                    .take_while(|line| {
-                        let line = line.as_ref().unwrap();
-                        // println!("{} is empty? {}", line, line.is_empty());
-                        !line.is_empty()
-                    })
-                    .map(Result::unwrap)
-                    .collect();
-                println!("[{}]: {:#?}", self.name, http_request);
-                let parsed = match parse_request(http_request) {
-                    Some(ep) => ep,
-                    None => break,
+                    Err(HeadError::TooLarge) => {
+                        let response = Response::new(431, "text/plain", "[ERROR] Request head too large".to_owned());
+                        let _ = stream.write_all(response.to_http(false, true).as_bytes());
+                        break;
+                    }
+                    Err(HeadError::Io(e)) => {
+                        println!("Connection {} failed: {}", self.name, e);
+                        break;
+                    }
                 };
-                let response_string = match get_response_string(&parsed, &db) {
-                    Some(rs) => rs,
-                    None => "Failed to parse OR no response required".to_owned(),
+                println!("[{}]: {:#?}", self.name, head);
+                // No endpoint reads a request body, but it must be consumed so the next request is read from its start
+                if let Some(length) = http::content_length(&head) {
+                    if let Err(e) = io::copy(&mut buf_reader.by_ref().take(length as u64), &mut io::sink()) {
+                        println!("Connection {} failed: {}", self.name, e);
+                        break;
+                    }
+                }
+                // Echoed even if the request turns out to be malformed, so the client can tell which one failed
+                let request_id = http::header(&head, http::REQUEST_ID_HEADER).map(String::from);
+                let (mut response, keep_alive, include_body) = match Endpoint::try_from(head) {
+                    Ok(request) => (
+                        get_response(&request, db.as_ref()),
+                        request.keep_alive(),
+                        request.method != "HEAD",
+                    ),
+                    // The request could not be understood, so the connection is closed rather than guessing where
+                    // the next one starts
+                    Err(e) => (get_error_response(&e.into()), false, true),
                 };
+                if let Some(request_id) = request_id {
+                    response.headers.push((http::REQUEST_ID_HEADER, request_id));
+                }
+                let response_string = response.to_http(keep_alive, include_body);
                 println_then_show_input_indicator(format!("RESPONSE:\n{}", response_string));
-                self.stream
-                    .as_ref()
-                    .unwrap()
-                    .write_all(response_string.as_bytes())
-                    .unwrap();
+                if let Err(e) = stream.write_all(response_string.as_bytes()) {
+                    println!("Connection {} failed: {}", self.name, e);
+                    break;
+                }
+                if !keep_alive {
+                    println!("Closing connection: {}", self.name);
+                    break;
+                }
 
                 /*Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                     if shutdown_trigger.load(Ordering::Relaxed) {
@@ -133,73 +176,282 @@ impl Connection {
     }
 }
 
-fn parse_request(request: Vec<String>) -> Option<Endpoint> {
-    if request.is_empty() {
-        return None;
+// Any error a request can fail with, sent back to the client instead of being unwrapped
+#[derive(Debug)]
+enum RequestError {
+    Protocol(ProtocolError),
+    Db(DbError),
+}
+impl RequestError {
+    fn kind(&self) -> &'static str {
+        match self {
+            RequestError::Protocol(e) => e.kind(),
+            RequestError::Db(e) => e.kind(),
+        }
     }
-    let uri = (request[0].split(' ').collect::<Vec<&str>>()[1])[1..].to_owned();
-    let (uri, query_param_str) = match uri.split_once('?') {
-        Some(split) => split,
-        None => (uri.as_str(), ""),
+
+    fn status(&self) -> u16 {
+        // HTTP status code of the error response
+        match self {
+            RequestError::Protocol(ProtocolError::UnknownEndpoint(_)) | RequestError::Db(DbError::NotFound(_)) => 404,
+            RequestError::Protocol(ProtocolError::MethodNotAllowed(_)) => 405,
+            RequestError::Protocol(ProtocolError::UnsupportedVersion(_)) => 505,
+            RequestError::Protocol(_) | RequestError::Db(DbError::InvalidArgument(_)) => 400,
+            RequestError::Db(DbError::Unsupported(_)) => 501,
+            RequestError::Db(DbError::Sqlite(_)) => 500,
+        }
+    }
+}
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RequestError::Protocol(e) => write!(f, "{}", e),
+            RequestError::Db(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl From<ProtocolError> for RequestError {
+    fn from(value: ProtocolError) -> Self {
+        RequestError::Protocol(value)
+    }
+}
+impl From<DbError> for RequestError {
+    fn from(value: DbError) -> Self {
+        RequestError::Db(value)
+    }
+}
+
+fn render_error(error: &RequestError, format: OutputFormat) -> String {
+    // Text keeps the "[ERROR] ..." line the client CLI displays; other formats get a one-row (error, message) table
+    // so that clients parsing e.g. JSON can tell an error from a result
+    match format {
+        OutputFormat::Text => format!("[ERROR] {}: {}", error.kind(), error),
+        _ => format.render(&ResultTable::new(
+            &["error", "message"],
+            vec![vec![
+                Value::Text(error.kind().to_owned()),
+                Value::Text(error.to_string()),
+            ]],
+        )),
+    }
+}
+
+fn get_output_format(request: &Endpoint) -> Result<OutputFormat, ProtocolError> {
+    // The format= parameter takes precedence over the Accept header; defaults to text
+    if let Some(name) = request.query_pv_map.get("format").and_then(|f| f.first()) {
+        return OutputFormat::from_name(name).ok_or(ProtocolError::UnsupportedFormat(name.to_owned()));
+    }
+    Ok(request
+        .headers
+        .get("accept")
+        .and_then(|accept| OutputFormat::from_accept(accept))
+        .unwrap_or(OutputFormat::Text))
+}
+
+fn get_response(request: &Endpoint, db: &dyn PlayerStore) -> Response {
+    let format = match get_output_format(request) {
+        Ok(format) => format,
+        Err(e) => return get_error_response(&e.into()),
     };
-    // println!("Parsed uri: {}", uri);
-    let mut query_pv_map: QueryPVMap = HashMap::new();
-    for qp_str in query_param_str.split('&').collect::<Vec<&str>>() {
-        if qp_str.is_empty() {
-            break;
+    let mut response = match get_response_table(request, db) {
+        Ok(table) => Response::new(200, format.media_type(), format.render(&table)),
+        Err(e) => error_response(&e, format),
+    };
+    response.headers.extend(version_headers(request));
+    response
+}
+
+fn version_headers(request: &Endpoint) -> Vec<(&'static str, String)> {
+    // Tells the client which API version served the request, and if it is deprecated, where its successor is
+    // The handshake is answered with the version it agreed on instead, and is how a client moves off a deprecated
+    // version, so is not itself deprecated
+    if request.uri == version::HANDSHAKE_URI {
+        let agreed = request.query_pv_map.get("versions").and_then(|v| version::negotiate(v));
+        return agreed.map(|v| ("API-Version", v.to_owned())).into_iter().collect();
+    }
+    let mut headers = vec![("API-Version", request.api_version.clone())];
+    // Only a request for an existing endpoint has a successor to point at
+    let resolved = requests::clone_authoritative_endpoint_by_uri(&request.uri).is_some();
+    if resolved && version::status(&request.api_version) == Some(VersionStatus::Deprecated) {
+        let successor = Endpoint {
+            api_version: version::CURRENT_VERSION.to_owned(),
+            ..request.clone()
         };
-        let (qp, qvals) = qp_str.split_once('=').unwrap();
-        let qvals = qvals.split(',').map(String::from).collect();
-        query_pv_map.insert(qp.to_owned(), qvals);
+        let successor = successor.get_valued_uri().unwrap_or_else(|_| successor.get_path());
+        headers.push(("Deprecation", "true".to_owned()));
+        headers.push(("Link", format!("<{}>; rel=\"successor-version\"", successor)));
     }
-    let endpoint = Endpoint::new(uri, query_pv_map);
-    // println!("Parsed endpoint: {:#?}", endpoint);
-    Some(endpoint)
+    headers
 }
 
-#[allow(clippy::manual_map)]
-fn get_response_string(request: &Endpoint, db: &database::DB) -> Option<String> {
-    let mut response_string: Option<String> = None;
-    if request.uri == "get-all-players" {
-        // optional params: name
-        response_string = Some(if let Some(name) = request.query_pv_map.get("name") {
-            let name = if name.len() == 1 {
-                Some(name[0].clone().replace('+', " "))
-            } else {
-                None
-            };
-            db.get_all_players(name).unwrap()
-        } else {
-            db.get_all_players(None).unwrap()
-        });
-    } else if request.uri == "get-player" {
-        // optional params: player_id, statistics
-        let (player_id, statistics) = (
-            request.query_pv_map.get("player_id"),
-            request.query_pv_map.get("statistics"),
-        );
-        let (player_id_arg, statistics_arg) = (
-            match player_id {
-                Some(player_id) => Some(player_id[0].clone()),
-                None => None,
-            },
-            match statistics {
-                Some(statistics) => Some(statistics.clone()),
-                None => None,
-            },
-        );
-        let player = db.get_player(player_id_arg, statistics_arg).unwrap();
-        // println!("get_player result: \n{}", player);
-        response_string = Some(player);
+fn error_response(error: &RequestError, format: OutputFormat) -> Response {
+    let mut response = Response::new(error.status(), format.media_type(), render_error(error, format));
+    if let RequestError::Protocol(ProtocolError::MethodNotAllowed(_)) = error {
+        response.headers.push(("Allow", requests::ALLOWED_METHODS.join(", ")));
     }
-    if response_string.is_some() {
-        response_string.map(|mut rs| {
-            rs.push(char::from(requests::REQUEST_TERMINATOR));
-            rs
+    response
+}
+
+fn get_error_response(error: &RequestError) -> Response {
+    // For errors raised before the request's format is known
+    error_response(error, OutputFormat::Text)
+}
+
+fn explain_requested(request: &Endpoint) -> bool {
+    request
+        .query_pv_map
+        .get("explain")
+        .and_then(|e| e.first())
+        .is_some_and(|e| e == "true" || e == "1")
+}
+
+fn get_response_table(request: &Endpoint, db: &dyn PlayerStore) -> Result<ResultTable, RequestError> {
+    // explain=true responds with the EXPLAIN QUERY PLAN of the queries the request ran, rather than their results
+    let explain = explain_requested(request);
+    db.clear_prepared_sql();
+    // explain=true must run the request's queries, so it always bypasses the cache
+    let cache_key = if explain { None } else { QueryCache::key(request) };
+    let (cached, generation) = {
+        let mut cache = QUERY_CACHE.lock().unwrap();
+        (cache_key.as_ref().and_then(|key| cache.get(key)), cache.generation())
+    };
+    if let Some(table) = cached {
+        return Ok(table);
+    }
+    let changes_before = db.total_changes().ok();
+    let result = run_request(request, db);
+    // Any write (or failing to tell whether one happened) invalidates every cached result
+    if changes_before.is_none() || db.total_changes().ok() != changes_before {
+        QUERY_CACHE.lock().unwrap().invalidate();
+    } else if let (Some(key), Ok(table)) = (cache_key, &result) {
+        QUERY_CACHE.lock().unwrap().insert(key, table.clone(), generation);
+    }
+    let table = result?;
+    if explain {
+        return Ok(db.explain_query_plan()?);
+    }
+    Ok(table)
+}
+
+fn run_request(request: &Endpoint, db: &dyn PlayerStore) -> Result<ResultTable, RequestError> {
+    // After validation, every parameter the schema requires is present, and optional ones with a default are filled
+    let request = &request.validated()?;
+    if explain_requested(request) && requests::WRITE_URIS.contains(&request.uri.as_str()) {
+        return Err(ProtocolError::UnexplainableEndpoint(request.uri.clone()).into());
+    }
+    let single = |p: &str| request.query_pv_map.get(p).and_then(|v| v.first().cloned());
+    let required = |p: &'static str| {
+        single(p).ok_or(ProtocolError::MissingParameter {
+            endpoint: request.uri.clone(),
+            parameter: p.to_owned(),
         })
+    };
+    let required_list = |p: &'static str| {
+        request
+            .query_pv_map
+            .get(p)
+            .cloned()
+            .ok_or(ProtocolError::MissingParameter {
+                endpoint: request.uri.clone(),
+                parameter: p.to_owned(),
+            })
+    };
+    Ok(if request.uri == "get-all-players" {
+        // optional params: name, position
+        db.get_all_players(single("name"), single("position"))?
+    } else if request.uri == "get-player" {
+        // optional params: player_id, statistics, position
+        db.get_player(
+            single("player_id"),
+            request.query_pv_map.get("statistics").cloned(),
+            single("position"),
+        )?
+    } else if request.uri == "best-xi" {
+        // required params: formation, score; optional params: club
+        db.best_xi(required("formation")?, single("club"), required_list("score")?)?
+    } else if request.uri == "fantasy-points" {
+        // optional params: ruleset, club, position, limit
+        db.fantasy_points(single("ruleset"), single("club"), single("position"), single("limit"))?
+    } else if request.uri == "set-sub-positions" {
+        // required params: player_id; optional params: sub_positions (empty to clear)
+        let sub_positions = request.query_pv_map.get("sub_positions").cloned().unwrap_or_default();
+        db.set_sub_positions(required("player_id")?, sub_positions)?
+    } else if request.uri == "aggregate" {
+        // required params: group_by, stats; optional params: fn (defaults to sum)
+        db.aggregate(required("group_by")?, required_list("stats")?, required("fn")?)?
+    } else if request.uri == "distribution" {
+        // required params: statistic; optional params: position, club, bins
+        db.distribution(
+            required("statistic")?,
+            single("position"),
+            single("club"),
+            single("bins"),
+        )?
+    } else if request.uri == "correlations" {
+        // required params: statistics; optional params: method, position, club, view, top
+        db.correlations(
+            required_list("statistics")?,
+            single("method"),
+            single("position"),
+            single("club"),
+            single("view"),
+            single("top"),
+        )?
+    } else if request.uri == "create-watchlist" {
+        // required params: user, list
+        db.create_watchlist(required("user")?, required("list")?)?
+    } else if request.uri == "get-watchlists" {
+        // required params: user
+        db.get_watchlists(required("user")?)?
+    } else if request.uri == "get-watchlist" {
+        // required params: user, list; optional params: statistics
+        db.get_watchlist(
+            required("user")?,
+            required("list")?,
+            request.query_pv_map.get("statistics").cloned(),
+        )?
+    } else if request.uri == "add-to-watchlist" {
+        // required params: user, list, player_id
+        db.add_to_watchlist(required("user")?, required("list")?, required_list("player_id")?)?
+    } else if request.uri == "remove-from-watchlist" {
+        // required params: user, list, player_id
+        db.remove_from_watchlist(required("user")?, required("list")?, required_list("player_id")?)?
+    } else if request.uri == "set-note" {
+        // required params: user, player_id; optional params: note, tags (both empty to remove the note)
+        db.set_note(
+            required("user")?,
+            required("player_id")?,
+            single("note"),
+            request.query_pv_map.get("tags").cloned().unwrap_or_default(),
+        )?
+    } else if request.uri == "save-query" {
+        // required params: name, query
+        let query = required("query")?;
+        requests::parse_saved_query(&query)?;
+        db.save_query(required("name")?, query)?
+    } else if request.uri == "run-query" {
+        // required params: name; plus one per placeholder of the saved query
+        // The filled in query is checked again when it is run, now that every value is known
+        let saved = requests::parse_saved_query(&db.saved_query(&required("name")?)?)?;
+        let mut filled = requests::fill_placeholders(&saved, request)?;
+        // explain applies to the saved query, so it is rejected there if that query writes
+        if let Some(explain) = request.query_pv_map.get("explain") {
+            filled.query_pv_map.insert("explain".to_owned(), explain.clone());
+        }
+        run_request(&filled, db)?
+    } else if request.uri == "get-saved-queries" {
+        db.get_saved_queries()?
+    } else if request.uri == "endpoints" {
+        requests::endpoints_table()
+    } else if request.uri == version::HANDSHAKE_URI {
+        // required params: versions
+        version::handshake(&required_list("versions")?)?
+    } else if request.uri == "validate" {
+        // optional params: rule
+        db.validate(request.query_pv_map.get("rule").cloned())?
     } else {
-        None
-    }
+        return Err(ProtocolError::UnknownEndpoint(request.uri.clone()).into());
+    })
 }
 
 fn cleanup(cli_thread_handle: JoinHandle<()>, connections: Arc<RwLock<Vec<Connection>>>) {
@@ -218,7 +470,27 @@ fn parse_input(buf: &str) -> Option<InputAction> {
     common::parse_input_action(&argsplit)
 }
 
-fn run_cli(shutdown_trigger: Arc<AtomicBool>, stream_handles: Arc<RwLock<Vec<Connection>>>) {
+fn print_maintenance_result(result: Result<String, DbError>) {
+    match result {
+        Ok(message) => println!("{}", message),
+        Err(e) => println!("[ERROR] {}", e),
+    }
+}
+
+fn open_sqlite(backend: &Backend, command: &str) -> Result<database::DB, DbError> {
+    // Maintenance commands operate on soccer.db itself, so have nothing to act on with the in-memory backend
+    match backend {
+        Backend::Sqlite => database::DB::new(),
+        _ => Err(DbError::Unsupported(format!(
+            "{} requires the sqlite backend ({} is {})",
+            command,
+            database::BACKEND_ENV_VAR,
+            backend.name()
+        ))),
+    }
+}
+
+fn run_cli(shutdown_trigger: Arc<AtomicBool>, stream_handles: Arc<RwLock<Vec<Connection>>>, backend: Backend) {
     let mut buf: String = String::new();
     loop {
         if shutdown_trigger.load(Ordering::Relaxed) {
@@ -237,6 +509,35 @@ fn run_cli(shutdown_trigger: Arc<AtomicBool>, stream_handles: Arc<RwLock<Vec<Con
                 InputAction::ListConnections => {
                     println!("Connections: {:#?}", stream_handles.read().unwrap());
                 }
+                InputAction::Validate => match backend.open().and_then(|db| db.validate(None)) {
+                    Ok(report) => println!(
+                        "{}\n{}",
+                        database::summarize_validation(&report),
+                        OutputFormat::Text.render(&report)
+                    ),
+                    Err(e) => println!("[ERROR] {}", e),
+                },
+                // The path is the rest of the line, so it may contain spaces
+                InputAction::Backup => match buf
+                    .split_once(' ')
+                    .map(|(_, path)| path.trim())
+                    .filter(|p| !p.is_empty())
+                {
+                    Some(path) => {
+                        print_maintenance_result(open_sqlite(&backend, "backup").and_then(|db| db.backup(path)))
+                    }
+                    None => println!("[ERROR] Usage: backup <PATH>"),
+                },
+                InputAction::Vacuum => {
+                    print_maintenance_result(open_sqlite(&backend, "vacuum").and_then(|db| db.vacuum()))
+                }
+                InputAction::Analyze => {
+                    print_maintenance_result(open_sqlite(&backend, "analyze").and_then(|db| db.analyze()))
+                }
+                InputAction::IntegrityCheck => print_maintenance_result(
+                    open_sqlite(&backend, "integrity-check").and_then(|db| db.integrity_check()),
+                ),
+                InputAction::CacheStats => println!("{}", QUERY_CACHE.lock().unwrap().stats()),
             }
         }
     }
@@ -254,28 +555,76 @@ pub fn run(init_db: Option<bool>) {
         }
     })
     .unwrap();
+    let duplicate_policy = match std::env::var(database::DUPLICATES_ENV_VAR) {
+        Err(_) => DuplicatePolicy::Merge,
+        Ok(name) => match DuplicatePolicy::from_name(&name) {
+            Some(policy) => policy,
+            None => {
+                println!(
+                    "[ERROR] Unknown {} '{}': expected merge or keep",
+                    database::DUPLICATES_ENV_VAR,
+                    name
+                );
+                return;
+            }
+        },
+    };
+    let backend = match std::env::var(database::BACKEND_ENV_VAR).as_deref() {
+        Ok("sqlite") | Err(_) => Backend::Sqlite,
+        Ok("memory") => {
+            // soccer.db is neither read nor written, so init-db has no effect
+            println!("Loading 'soccer.csv' into memory...");
+            match MemoryStore::load(duplicate_policy) {
+                Ok(store) => Backend::Memory(store),
+                Err(e) => {
+                    println!("[ERROR] {}", e);
+                    return;
+                }
+            }
+        }
+        Ok(other) => {
+            println!(
+                "[ERROR] Unknown {} '{}': expected sqlite or memory",
+                database::BACKEND_ENV_VAR,
+                other
+            );
+            return;
+        }
+    };
+    println!("Using {} backend", backend.name());
     let _path = std::path::Path::new("soccer.db");
-    if init_db.is_some_and(|b| b) || !_path.exists() {
+    if matches!(backend, Backend::Sqlite) && (init_db.is_some_and(|b| b) || !_path.exists()) {
         println!("Database initializating...");
         println!("Running initialization (conversion of 'soccer.csv' -> 'soccer.db'...");
-        database::csv_to_sqlite();
+        if let Err(e) = database::csv_to_sqlite(duplicate_policy) {
+            println!("[ERROR] {}", e);
+            return;
+        }
+        QUERY_CACHE.lock().unwrap().invalidate();
     }
     println!("Starting server...");
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    listener.set_nonblocking(true).expect("Cannot set non-blocking");
+    // Without a listener there is nothing to serve, e.g. when another server already holds the address
+    let listener = match TcpListener::bind(SERVER_ADDR).and_then(|l| l.set_nonblocking(true).map(|_| l)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("[ERROR] Cannot listen on {}: {}", SERVER_ADDR, e);
+            std::process::exit(1);
+        }
+    };
     let connections: Arc<RwLock<Vec<Connection>>> = Arc::new(RwLock::new(vec![]));
     let cli_connections: Arc<RwLock<Vec<Connection>>> = connections.clone();
     println!("Server started successfully!");
 
     // Initialize Server CLI IO
-    let cli_thread_handle = thread::spawn(|| run_cli(cli_shutdown_trigger, cli_connections));
+    let cli_backend = backend.clone();
+    let cli_thread_handle = thread::spawn(|| run_cli(cli_shutdown_trigger, cli_connections, cli_backend));
     // Connection listener loop
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
                 let conn = Connection::new(stream, Some(shutdown_trigger.clone()));
                 println_then_show_input_indicator(format!("Incoming connection from: {}", conn.name));
-                connections.write().unwrap().push(conn.start_thread());
+                connections.write().unwrap().push(conn.start_thread(backend.clone()));
             }
             Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
                 // println!("WOULD BLOCK: {}", e);
@@ -286,7 +635,8 @@ pub fn run(init_db: Option<bool>) {
                 thread::sleep(Duration::from_millis(50));
                 continue;
             }
-            Err(e) => panic!("Encountered IO error: {}", e),
+            // A failed accept only affects the connection being accepted
+            Err(e) => println_then_show_input_indicator(format!("[ERROR] Failed to accept connection: {}", e)),
         }
     }
     shutdown_trigger.store(true, Ordering::Relaxed);
@@ -0,0 +1,77 @@
+use crate::{database::format::ResultTable, requests::ProtocolError};
+use rusqlite::types::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VersionStatus {
+    Current,
+    // Still served, with a Deprecation header pointing at the current version, until dropped from VERSION_DEFS
+    Deprecated,
+}
+impl VersionStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            VersionStatus::Current => "current",
+            VersionStatus::Deprecated => "deprecated",
+        }
+    }
+}
+
+// API versions the server speaks, as they appear in request paths (e.g. /v1/get-player), oldest first
+// Format: (version, status)
+// v0 is the unversioned paths (e.g. /get-player) of clients predating the handshake. A deprecated version is served
+// for at least one release after it is deprecated, then removed here
+pub const VERSION_DEFS: [(&str, VersionStatus); 2] =
+    [("v0", VersionStatus::Deprecated), ("v1", VersionStatus::Current)];
+
+// The version requests built by this build are sent as, unless the handshake agrees on another
+pub const CURRENT_VERSION: &str = "v1";
+
+// The version requests without a version segment are served as
+pub const UNVERSIONED: &str = "v0";
+
+// Endpoint agreeing on a version, reachable at every version (and none) so a client can use it before it knows one
+pub const HANDSHAKE_URI: &str = "handshake";
+
+// Column names of the handshake's response
+pub const HANDSHAKE_COLUMNS: [&str; 2] = ["version", "status"];
+
+pub fn status(version: &str) -> Option<VersionStatus> {
+    VERSION_DEFS.iter().find(|(v, _)| *v == version).map(|(_, s)| *s)
+}
+
+pub fn version_names() -> Vec<&'static str> {
+    VERSION_DEFS.iter().map(|(v, _)| *v).collect()
+}
+
+pub fn is_version_segment(segment: &str) -> bool {
+    // e.g. v1; endpoint names never take this form, so it cannot be mistaken for one
+    segment
+        .strip_prefix('v')
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+pub fn split_path(path: &str) -> (Option<&str>, &str) {
+    // Splits a request path (without its leading '/') into its version segment, if any, and the endpoint uri
+    match path.split_once('/') {
+        Some((segment, uri)) if is_version_segment(segment) => (Some(segment), uri),
+        _ => (None, path),
+    }
+}
+
+pub fn negotiate(offered: &[String]) -> Option<&'static str> {
+    // The newest version both sides speak, regardless of the order the client offered them in
+    VERSION_DEFS
+        .iter()
+        .rev()
+        .map(|(v, _)| *v)
+        .find(|v| offered.iter().any(|o| o.trim() == *v))
+}
+
+pub fn handshake(offered: &[String]) -> Result<ResultTable, ProtocolError> {
+    let version = negotiate(offered).ok_or(ProtocolError::UnsupportedApiVersion(offered.join(", ")))?;
+    let status = status(version).map_or("", |s| s.name());
+    Ok(ResultTable::new(
+        &HANDSHAKE_COLUMNS,
+        vec![vec![Value::Text(version.to_owned()), Value::Text(status.to_owned())]],
+    ))
+}